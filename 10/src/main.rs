@@ -1,85 +1,641 @@
-use std::collections::VecDeque;
-use std::io::Result;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
 
 use read_input::read_text;
 
-fn get_closing_for_opening(opening: char) -> char {
-    match opening {
-        '(' => ')',
-        '[' => ']',
-        '{' => '}',
-        '<' => '>',
-        _ => {
-            panic!("Unrecognized character: {}", opening);
+// The standard pairs with their corruption scores and completion weights
+const DEFAULT_CONFIG: &str = "base 5
+( ) 3 1
+[ ] 57 2
+{ } 1197 3
+< > 25137 4";
+
+#[derive(Clone, Debug, PartialEq)]
+struct Pair {
+    opening: char,
+    closing: char,
+    // what finding this closer in the wrong place scores
+    corruption_score: usize,
+    // what each missing closer of this kind adds to a completion score
+    completion_weight: usize,
+}
+
+#[derive(Debug, PartialEq)]
+struct SyntaxConfig {
+    pairs: Vec<Pair>,
+    // completion scores are multiplied by this before adding each closer
+    completion_base: usize,
+}
+
+impl SyntaxConfig {
+    // Parses a "base <n>" line and one "<opening> <closing> <corruption
+    // score> <completion weight>" line per pair
+    fn parse(text: &str) -> std::result::Result<Self, String> {
+        let mut completion_base = None;
+        let mut pairs: Vec<Pair> = Vec::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["base", base] => {
+                    completion_base = Some(
+                        base.parse::<usize>()
+                            .map_err(|_| format!("invalid base {}", base))?,
+                    )
+                }
+                [opening, closing, corruption_score, completion_weight] => {
+                    let single = |field: &str| {
+                        let mut chars = field.chars();
+                        match (chars.next(), chars.next()) {
+                            (Some(ch), None) => Ok(ch),
+                            _ => Err(format!("{} isn't a single character", field)),
+                        }
+                    };
+                    let number = |field: &str| {
+                        field
+                            .parse::<usize>()
+                            .map_err(|_| format!("invalid score {}", field))
+                    };
+                    let pair = Pair {
+                        opening: single(opening)?,
+                        closing: single(closing)?,
+                        corruption_score: number(corruption_score)?,
+                        completion_weight: number(completion_weight)?,
+                    };
+
+                    let used = |ch: char| {
+                        ch == pair.opening && ch == pair.closing
+                            || pairs
+                                .iter()
+                                .any(|other| other.opening == ch || other.closing == ch)
+                    };
+                    if let Some(ch) = [pair.opening, pair.closing]
+                        .into_iter()
+                        .find(|ch| used(*ch))
+                    {
+                        return Err(format!("{} is used more than once", ch));
+                    }
+                    pairs.push(pair);
+                }
+                _ => return Err(format!("unrecognised line {}", line)),
+            }
+        }
+
+        if pairs.is_empty() {
+            return Err("a config needs at least one pair".to_string());
         }
+        Ok(SyntaxConfig {
+            pairs,
+            completion_base: completion_base.ok_or("a config needs a base")?,
+        })
+    }
+
+    fn opening(&self, ch: char) -> Option<&Pair> {
+        self.pairs.iter().find(|pair| pair.opening == ch)
+    }
+
+    fn closing(&self, ch: char) -> Option<&Pair> {
+        self.pairs.iter().find(|pair| pair.closing == ch)
+    }
+
+    // None when the score is too large for a usize, which a large base
+    // reaches within a few closers
+    fn completion_score(&self, completion: &str) -> Option<usize> {
+        completion.chars().try_fold(0usize, |score, ch| {
+            score
+                .checked_mul(self.completion_base)?
+                .checked_add(self.closing(ch).unwrap().completion_weight)
+        })
     }
 }
 
-fn closes_the_opening_bracket(opening: char, closing: char) -> bool {
-    get_closing_for_opening(opening) == closing
+impl Default for SyntaxConfig {
+    fn default() -> Self {
+        SyntaxConfig::parse(DEFAULT_CONFIG).unwrap()
+    }
 }
 
-fn main() -> Result<()> {
-    let text = read_text("10/input.txt")?;
+// Columns count characters from 1
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Valid,
+    // the first closer that doesn't match the innermost open bracket
+    Corrupted {
+        column: usize,
+        expected: char,
+        found: char,
+        // where the bracket `expected` would have closed was opened
+        opened_at: usize,
+    },
+    Incomplete {
+        // the closers that would finish the line, innermost first
+        completion: String,
+        // where each bracket the completion closes was opened, innermost first
+        unmatched: Vec<usize>,
+    },
+    // a closer with nothing left open
+    OverClosed {
+        column: usize,
+        found: char,
+    },
+    // a character that isn't one of the brackets, like the \r of a CRLF line
+    Invalid {
+        column: usize,
+        found: char,
+    },
+}
 
-    let mut illegal_chars = Vec::new();
-    let mut p2_scores = Vec::new();
-    for line in text.lines() {
-        let mut open_brackets = VecDeque::new();
-        let mut is_illegal = false;
-        for ch in line.chars() {
-            match ch {
-                '(' | '[' | '{' | '<' => {
-                    open_brackets.push_back(ch);
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Valid => write!(f, "valid"),
+            Outcome::Corrupted {
+                column,
+                expected,
+                found,
+                opened_at,
+            } => write!(
+                f,
+                "corrupted at column {}, expected {} to close column {} but found {}",
+                column, expected, opened_at, found
+            ),
+            Outcome::Incomplete {
+                completion,
+                unmatched,
+            } => write!(
+                f,
+                "incomplete, {} closes the brackets opened at columns {}",
+                completion,
+                unmatched
+                    .iter()
+                    .map(|column| column.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Outcome::OverClosed { column, found } => write!(
+                f,
+                "over-closed at column {}, found {} with nothing open",
+                column, found
+            ),
+            Outcome::Invalid { column, found } => write!(
+                f,
+                "invalid at column {}, found {:?} which isn't a bracket",
+                column, found
+            ),
+        }
+    }
+}
+
+fn check(config: &SyntaxConfig, line: &str) -> Outcome {
+    // the open brackets with the columns they're at
+    let mut open_brackets: Vec<(&Pair, usize)> = Vec::new();
+    for (i, ch) in line.chars().enumerate() {
+        let column = i + 1;
+        if let Some(pair) = config.opening(ch) {
+            open_brackets.push((pair, column));
+        } else if config.closing(ch).is_some() {
+            match open_brackets.pop() {
+                Some((opening, opened_at)) if opening.closing != ch => {
+                    return Outcome::Corrupted {
+                        column,
+                        expected: opening.closing,
+                        found: ch,
+                        opened_at,
+                    };
                 }
-                ')' | ']' | '}' | '>' => {
-                    let opening = open_brackets.pop_back().unwrap();
-                    if !closes_the_opening_bracket(opening, ch) {
-                        is_illegal = true;
-                        illegal_chars.push(ch);
-                        break;
-                    }
+                Some(_) => {}
+                None => return Outcome::OverClosed { column, found: ch },
+            }
+        } else {
+            return Outcome::Invalid { column, found: ch };
+        }
+    }
+
+    if open_brackets.is_empty() {
+        Outcome::Valid
+    } else {
+        Outcome::Incomplete {
+            completion: open_brackets
+                .iter()
+                .rev()
+                .map(|(pair, _)| pair.closing)
+                .collect(),
+            unmatched: open_brackets
+                .iter()
+                .rev()
+                .map(|(_, column)| *column)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Repair {
+    line: String,
+    // characters inserted, deleted or replaced
+    edits: usize,
+}
+
+// How the part of a line starting at some character gets balanced
+#[derive(Clone, Copy)]
+enum Step {
+    Done,
+    // an opener wraps the rest of the part in a new closer, a closer is
+    // dropped
+    Lone,
+    // the first character pairs up with the one at this index, replacing
+    // either if needed
+    Pair(usize),
+}
+
+impl SyntaxConfig {
+    // What the two characters become to pair up, and how many of them change
+    fn pair_up(&self, first: char, second: char) -> (usize, char, char) {
+        match (self.opening(first), self.closing(second)) {
+            (Some(pair), _) if pair.closing == second => (0, first, second),
+            (Some(pair), _) => (1, first, pair.closing),
+            (None, Some(pair)) => (1, pair.opening, second),
+            (None, None) => {
+                let pair = self.closing(first).unwrap();
+                (2, pair.opening, pair.closing)
+            }
+        }
+    }
+}
+
+// Finds the fewest insertions, deletions and replacements that balance the
+// line. cost[i][j] is the cheapest way to balance chars[i..j], built up from
+// shorter parts, so this is O(n^3) in the length of the line.
+fn minimal_repair(config: &SyntaxConfig, line: &str) -> Repair {
+    let chars: Vec<char> = line.chars().collect();
+    let n = chars.len();
+    let mut cost = vec![vec![0; n + 1]; n + 1];
+    let mut steps = vec![vec![Step::Done; n + 1]; n + 1];
+    for len in 1..=n {
+        for i in 0..=n - len {
+            let j = i + len;
+            let mut best = (1 + cost[i + 1][j], Step::Lone);
+            for k in i + 1..j {
+                let (changes, _, _) = config.pair_up(chars[i], chars[k]);
+                let total = changes + cost[i + 1][k] + cost[k + 1][j];
+                if total < best.0 {
+                    best = (total, Step::Pair(k));
                 }
-                _ => {
-                    panic!("Unrecognized character: {}", ch);
+            }
+            cost[i][j] = best.0;
+            steps[i][j] = best.1;
+        }
+    }
+
+    fn build(
+        config: &SyntaxConfig,
+        chars: &[char],
+        steps: &[Vec<Step>],
+        i: usize,
+        j: usize,
+        out: &mut String,
+    ) {
+        match steps[i][j] {
+            Step::Done => {}
+            Step::Lone => {
+                if let Some(pair) = config.opening(chars[i]) {
+                    out.push(chars[i]);
+                    build(config, chars, steps, i + 1, j, out);
+                    out.push(pair.closing);
+                } else {
+                    build(config, chars, steps, i + 1, j, out);
                 }
             }
+            Step::Pair(k) => {
+                let (_, opening, closing) = config.pair_up(chars[i], chars[k]);
+                out.push(opening);
+                build(config, chars, steps, i + 1, k, out);
+                out.push(closing);
+                build(config, chars, steps, k + 1, j, out);
+            }
         }
+    }
 
-        if !is_illegal && open_brackets.len() > 0 {
-            let mut score: usize = 0;
-            while open_brackets.len() > 0 {
-                let opening = open_brackets.pop_back().unwrap();
-                let closing = get_closing_for_opening(opening);
-                score *= 5;
-                score += match closing {
-                    ')' => 1,
-                    ']' => 2,
-                    '}' => 3,
-                    '>' => 4,
-                    _ => {
-                        panic!("Unrecognized character: {}", closing);
-                    }
+    let mut repaired = String::with_capacity(n);
+    build(config, &chars, &steps, 0, n, &mut repaired);
+    Repair {
+        line: repaired,
+        edits: cost[0][n],
+    }
+}
+
+// Incomplete lines get their completion appended, corrupted and over-closed
+// ones the smallest edit that balances them. Lines with characters that aren't
+// brackets are left alone.
+fn repair(config: &SyntaxConfig, line: &str, outcome: &Outcome) -> Repair {
+    match outcome {
+        Outcome::Valid | Outcome::Invalid { .. } => Repair {
+            line: line.to_string(),
+            edits: 0,
+        },
+        Outcome::Incomplete { completion, .. } => Repair {
+            line: format!("{}{}", line, completion),
+            edits: completion.chars().count(),
+        },
+        Outcome::Corrupted { .. } | Outcome::OverClosed { .. } => minimal_repair(config, line),
+    }
+}
+
+// A hunk per changed line with the original and the repair
+fn write_diff(out: &mut impl Write, lines: &[&str], repairs: &[Repair]) -> Result<()> {
+    for (i, (line, repair)) in lines.iter().zip(repairs).enumerate() {
+        if repair.edits == 0 {
+            continue;
+        }
+        writeln!(
+            out,
+            "@@ line {}, {} edit{} @@",
+            i + 1,
+            repair.edits,
+            if repair.edits == 1 { "" } else { "s" }
+        )?;
+        writeln!(out, "-{}", line)?;
+        writeln!(out, "+{}", repair.line)?;
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let text = read_text("10/input.txt")?;
+
+    // --config PATH swaps in other brackets and scores, --report describes
+    // every line that isn't valid, --repair PATH writes every line fixed up
+    // and a diff of the fixes to PATH.diff
+    let mut config = SyntaxConfig::default();
+    let mut report = false;
+    let mut repair_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => {
+                let path = args.next().expect("--config needs a path");
+                config = match SyntaxConfig::parse(&read_text(&path)?) {
+                    Ok(config) => config,
+                    Err(reason) => panic!("Invalid syntax config: {}", reason),
                 };
             }
-
-            p2_scores.push(score);
+            "--report" => report = true,
+            "--repair" => repair_path = Some(args.next().expect("--repair needs a path")),
+            _ => panic!("Unknown argument {}", arg),
         }
     }
 
-    let p1_score = illegal_chars.iter().fold(0, |sum, ch| match ch {
-        ')' => sum + 3,
-        ']' => sum + 57,
-        '}' => sum + 1197,
-        '>' => sum + 25137,
-        _ => {
-            panic!("Unrecognized character: {}", ch);
+    let lines: Vec<&str> = text.lines().collect();
+    let outcomes: Vec<Outcome> = lines.iter().map(|line| check(&config, line)).collect();
+
+    let mut p1_score = 0;
+    let mut p2_scores = Vec::new();
+    for (i, outcome) in outcomes.iter().enumerate() {
+        match outcome {
+            Outcome::Corrupted { found, .. } => {
+                p1_score += config.closing(*found).unwrap().corruption_score;
+            }
+            Outcome::Incomplete { completion, .. } => match config.completion_score(completion) {
+                Some(score) => p2_scores.push(score),
+                None => panic!(
+                    "The completion {} of line {} scores more than fits in a usize",
+                    completion,
+                    i + 1
+                ),
+            },
+            Outcome::Valid | Outcome::OverClosed { .. } | Outcome::Invalid { .. } => {}
         }
-    });
+    }
     println!("{}", p1_score);
 
     p2_scores.sort();
     println!("{:?}", p2_scores.get(p2_scores.len() / 2));
 
+    if report {
+        for (i, outcome) in outcomes.iter().enumerate() {
+            if *outcome != Outcome::Valid {
+                println!("line {}: {}", i + 1, outcome);
+            }
+        }
+    }
+
+    if let Some(path) = repair_path {
+        let repairs: Vec<Repair> = lines
+            .iter()
+            .zip(&outcomes)
+            .map(|(line, outcome)| repair(&config, line, outcome))
+            .collect();
+
+        let mut out = BufWriter::new(File::create(&path)?);
+        for repair in &repairs {
+            writeln!(out, "{}", repair.line)?;
+        }
+        write_diff(
+            &mut BufWriter::new(File::create(format!("{}.diff", path))?),
+            &lines,
+            &repairs,
+        )?;
+
+        println!(
+            "repaired {} of {} lines with {} edits",
+            repairs.iter().filter(|repair| repair.edits > 0).count(),
+            repairs.len(),
+            repairs.iter().map(|repair| repair.edits).sum::<usize>()
+        );
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "[({(<(())[]>[[{[]{<()<>>
+[(()[<>])]({[<{<<[]>>(
+{([(<{}[<>[]}>{[]{[(<()>
+(((({<>}<{<{<>}{[]{[]{}
+[[<[([]))<([[{}[[()]]]
+[{[{({}]{}}([{[{{{}}([]
+{<[[]]>}<{[{[{[]{()[[[]
+[<(<(<(<{}))><([]([]()
+<{([([[(<>()){}]>(<<{{
+<{([{{}}[<[[[<>{}]]]>[]]";
+
+    #[test]
+    fn test_check() {
+        let config = SyntaxConfig::default();
+        let outcomes: Vec<Outcome> = EXAMPLE.lines().map(|line| check(&config, line)).collect();
+
+        let corrupted: usize = outcomes
+            .iter()
+            .filter_map(|outcome| match outcome {
+                Outcome::Corrupted { found, .. } => {
+                    Some(config.closing(*found).unwrap().corruption_score)
+                }
+                _ => None,
+            })
+            .sum();
+        assert_eq!(corrupted, 26397);
+
+        assert_eq!(
+            outcomes[0],
+            Outcome::Incomplete {
+                completion: "}}]])})]".to_string(),
+                unmatched: vec![18, 15, 14, 13, 4, 3, 2, 1],
+            }
+        );
+        assert_eq!(config.completion_score("}}]])})]"), Some(288957));
+        assert_eq!(check(&config, "<([]){()}[{}]>"), Outcome::Valid);
+    }
+
+    #[test]
+    fn test_custom_config() {
+        // slashes and guillemets, scored in base 10
+        let config = SyntaxConfig::parse(
+            "base 10
+/ \\ 7 1
+« » 11 2",
+        )
+        .unwrap();
+        assert_eq!(check(&config, "/«»\\"), Outcome::Valid);
+        assert!(matches!(
+            check(&config, "/«\\"),
+            Outcome::Corrupted { found: '\\', .. }
+        ));
+        assert!(matches!(
+            check(&config, "«/«"),
+            Outcome::Incomplete { completion, .. } if completion == "»\\»"
+        ));
+        assert_eq!(config.completion_score("»\\»"), Some(212));
+
+        // 20 * 1000^6 for the first of seven closers is past even a 64 bit
+        // usize
+        let config = SyntaxConfig::parse("base 1000\n( ) 1 20").unwrap();
+        assert_eq!(config.completion_score(")))))))"), None);
+        assert_eq!(config.completion_score(")))"), Some(20_020_020));
+
+        assert!(SyntaxConfig::parse("( ) 1 1").is_err());
+        assert!(SyntaxConfig::parse("base 5\n( ) 1 1\n) ] 1 1").is_err());
+        assert!(SyntaxConfig::parse("base 5\n| | 1 1").is_err());
+        assert!(SyntaxConfig::parse("base 5\n() ) 1 1").is_err());
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let config = SyntaxConfig::default();
+        assert_eq!(
+            check(&config, "{([(<{}[<>[]}>{[]{[(<()>"),
+            Outcome::Corrupted {
+                column: 13,
+                expected: ']',
+                found: '}',
+                opened_at: 8,
+            }
+        );
+        assert_eq!(
+            check(&config, "()]("),
+            Outcome::OverClosed {
+                column: 3,
+                found: ']',
+            }
+        );
+        assert_eq!(
+            check(&config, ")").to_string(),
+            "over-closed at column 1, found ) with nothing open"
+        );
+        assert_eq!(
+            check(&config, "[<>({").to_string(),
+            "incomplete, })] closes the brackets opened at columns 5, 4, 1"
+        );
+    }
+
+    #[test]
+    fn test_invalid_characters() {
+        let config = SyntaxConfig::default();
+        assert_eq!(
+            check(&config, "[<>]\r"),
+            Outcome::Invalid {
+                column: 5,
+                found: '\r',
+            }
+        );
+        assert_eq!(
+            check(&config, "(a)").to_string(),
+            "invalid at column 2, found 'a' which isn't a bracket"
+        );
+
+        // the lines after a bad one are still checked
+        let outcomes: Vec<Outcome> = "()\r\n(]\n"
+            .split('\n')
+            .map(|line| check(&config, line))
+            .collect();
+        assert!(matches!(outcomes[0], Outcome::Invalid { column: 3, .. }));
+        assert!(matches!(outcomes[1], Outcome::Corrupted { column: 2, .. }));
+
+        let line = "(\r";
+        let repair = repair(&config, line, &check(&config, line));
+        assert_eq!((repair.line.as_str(), repair.edits), (line, 0));
+    }
+
+    #[test]
+    fn test_minimal_repair() {
+        let config = SyntaxConfig::default();
+        let repaired = |line: &str| {
+            let repair = repair(&config, line, &check(&config, line));
+            (repair.line, repair.edits)
+        };
+        assert_eq!(repaired("(]"), ("()".to_string(), 1));
+        assert_eq!(repaired("[<>)"), ("[<>]".to_string(), 1));
+        assert_eq!(repaired("())"), ("()".to_string(), 1));
+        assert_eq!(repaired(")("), ("()".to_string(), 2));
+        assert_eq!(repaired("[({"), ("[({})]".to_string(), 3));
+        // dropping the stray closer beats replacing everything after it
+        assert_eq!(repaired("(()))()()"), ("(())()()".to_string(), 1));
+
+        for line in EXAMPLE.lines() {
+            let repair = repair(&config, line, &check(&config, line));
+            assert_eq!(check(&config, &repair.line), Outcome::Valid);
+            assert!(repair.edits <= greedy_edits(&config, line));
+        }
+    }
+
+    // Replaces each bad closer with the one expected, then completes the line
+    fn greedy_edits(config: &SyntaxConfig, line: &str) -> usize {
+        let mut line = line.to_string();
+        let mut edits = 0;
+        loop {
+            match check(config, &line) {
+                Outcome::Valid => return edits,
+                Outcome::Incomplete { completion, .. } => return edits + completion.len(),
+                Outcome::Corrupted {
+                    column, expected, ..
+                } => {
+                    let mut chars: Vec<char> = line.chars().collect();
+                    chars[column - 1] = expected;
+                    line = chars.into_iter().collect();
+                }
+                Outcome::OverClosed { column, .. } | Outcome::Invalid { column, .. } => {
+                    line.remove(column - 1);
+                }
+            }
+            edits += 1;
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let config = SyntaxConfig::default();
+        let lines = ["()", "(]", "(("];
+        let repairs: Vec<Repair> = lines
+            .iter()
+            .map(|line| repair(&config, line, &check(&config, line)))
+            .collect();
+        let mut out = Vec::new();
+        write_diff(&mut out, &lines, &repairs).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "@@ line 2, 1 edit @@\n-(]\n+()\n@@ line 3, 2 edits @@\n-((\n+(())\n"
+        );
+    }
+}
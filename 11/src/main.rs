@@ -3,92 +3,397 @@ use std::io::Result;
 
 use read_input::read_text;
 
-fn get_surrounding_octopi(
-    octopi: &HashMap<(usize, usize), u32>,
-    center: &(usize, usize),
-) -> Vec<(usize, usize)> {
-    let mut surrounding = Vec::new();
-
-    for offset_x in -1i32..=1i32 {
-        for offset_y in -1i32..=1i32 {
-            if offset_x == 0 && offset_y == 0 {
-                continue;
-            }
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Edges {
+    Bounded,
+    // the grid wraps around, left to right and top to bottom
+    Torus,
+}
 
-            let x = (center.0 as i32 + offset_x) as usize;
-            let y = (center.1 as i32 + offset_y) as usize;
-            if let Some(_octopus) = octopi.get(&(x, y)) {
-                surrounding.push((x, y));
-            }
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Neighbourhood {
+    // sides only
+    Four,
+    // sides and corners
+    Eight,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Rules {
+    edges: Edges,
+    neighbourhood: Neighbourhood,
+    // octopuses flash once their energy goes past this
+    flash_threshold: u32,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            edges: Edges::Bounded,
+            neighbourhood: Neighbourhood::Eight,
+            flash_threshold: 9,
         }
     }
+}
 
-    surrounding
+struct Octopi {
+    flash_threshold: u32,
+    // row by row
+    energy: Vec<u32>,
+    // neighbours[starts[i]..starts[i + 1]] are the cells next to cell i
+    starts: Vec<usize>,
+    neighbours: Vec<usize>,
+    // reused between steps for the cells still to flash
+    pending: Vec<usize>,
 }
 
-fn main() -> Result<()> {
-    let text = read_text("11/input.txt")?;
+impl Octopi {
+    fn parse(text: &str, rules: &Rules) -> Self {
+        let rows: Vec<Vec<u32>> = text
+            .lines()
+            .map(|line| line.chars().map(|ch| ch.to_digit(10).unwrap()).collect())
+            .collect();
+        let height = rows.len();
+        let width = rows.first().map_or(0, |row| row.len());
+        if rows.iter().any(|row| row.len() != width) {
+            panic!("Every row needs {} octopuses", width);
+        }
 
-    let mut grid = HashMap::new();
+        let offsets: &[(isize, isize)] = match rules.neighbourhood {
+            Neighbourhood::Four => &[(0, -1), (-1, 0), (1, 0), (0, 1)],
+            Neighbourhood::Eight => &[
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+                (-1, 0),
+                (1, 0),
+                (-1, 1),
+                (0, 1),
+                (1, 1),
+            ],
+        };
+        // None when the offset leaves a bounded grid
+        let shift = |at: usize, by: isize, len: usize| match rules.edges {
+            Edges::Bounded => at.checked_add_signed(by).filter(|at| *at < len),
+            Edges::Torus => Some((at as isize + by).rem_euclid(len as isize) as usize),
+        };
 
-    for (row, line) in text.lines().enumerate() {
-        for (col, ch) in line.chars().enumerate() {
-            grid.insert((col, row), ch.to_digit(10).unwrap());
+        // On a torus narrower than three cells the same octopus can be next
+        // to another one from two sides, or next to itself. It only counts
+        // once, and never as its own neighbour.
+        let mut starts = vec![0];
+        let mut neighbours = Vec::new();
+        for row in 0..height {
+            for col in 0..width {
+                let cell = row * width + col;
+                let start = neighbours.len();
+                for (offset_col, offset_row) in offsets {
+                    if let (Some(neighbour_col), Some(neighbour_row)) = (
+                        shift(col, *offset_col, width),
+                        shift(row, *offset_row, height),
+                    ) {
+                        let neighbour = neighbour_row * width + neighbour_col;
+                        if neighbour != cell && !neighbours[start..].contains(&neighbour) {
+                            neighbours.push(neighbour);
+                        }
+                    }
+                }
+                starts.push(neighbours.len());
+            }
+        }
+
+        Octopi {
+            flash_threshold: rules.flash_threshold,
+            energy: rows.into_iter().flatten().collect(),
+            starts,
+            neighbours,
+            pending: Vec::new(),
         }
     }
 
-    let mut flash_count = 0;
-    let mut step_count = 0;
-    loop {
-        for (_, octopus) in &mut grid {
-            *octopus += 1;
+    fn len(&self) -> usize {
+        self.energy.len()
+    }
+
+    // Advances one step and returns how many octopuses flashed. Each octopus
+    // is queued the moment it passes the threshold, so it flashes once and a
+    // step costs one visit per cell plus one per neighbour of each flash.
+    fn step(&mut self) -> usize {
+        let threshold = self.flash_threshold;
+        for (i, energy) in self.energy.iter_mut().enumerate() {
+            *energy += 1;
+            // starting energies can already be well past a low threshold
+            if *energy > threshold {
+                self.pending.push(i);
+            }
         }
 
-        loop {
-            let mut next_grid = grid.clone();
-            let mut should_continue = false;
-            for (coord, octopus) in &grid {
-                if *octopus > 9 {
-                    // condition for p1
-                    if step_count < 100 {
-                        flash_count += 1;
-                    }
-                    // mark this one as flashed
-                    next_grid.insert(coord.to_owned(), 0);
-                    for neighbour_coord in &get_surrounding_octopi(&grid, coord) {
-                        if let Some(octopus) = next_grid.get_mut(neighbour_coord) {
-                            if *octopus > 0 {
-                                *octopus += 1;
-                                if *octopus > 9 {
-                                    should_continue = true;
-                                }
-                            }
-                        }
+        let mut flashed = 0;
+        while let Some(i) = self.pending.pop() {
+            flashed += 1;
+            for &neighbour in &self.neighbours[self.starts[i]..self.starts[i + 1]] {
+                let energy = &mut self.energy[neighbour];
+                if *energy <= threshold {
+                    *energy += 1;
+                    if *energy > threshold {
+                        self.pending.push(neighbour);
                     }
                 }
             }
+        }
 
-            grid = next_grid;
-            if !should_continue {
-                break;
+        for energy in &mut self.energy {
+            if *energy > threshold {
+                *energy = 0;
             }
         }
-        if step_count == 99 {
-            println!("p1 {}", flash_count);
+
+        flashed
+    }
+}
+
+// Every octopus's energy stays within the threshold between steps, so there
+// are finitely many states and the grid has to come back to one eventually.
+// From then on it repeats the same `length` steps forever.
+#[derive(Debug, PartialEq)]
+struct Cycle {
+    // the grid after this many steps is the first state that comes back
+    start: usize,
+    length: usize,
+    // flashes[i] is how many octopuses flash in step i + 1, up to the end of
+    // the first time round the cycle
+    flashes: Vec<usize>,
+    octopuses: usize,
+}
+
+impl Cycle {
+    // Steps until a state repeats, remembering each state by its energies
+    fn find(mut octopi: Octopi) -> Self {
+        let mut seen = HashMap::from([(octopi.energy.clone(), 0)]);
+        let mut flashes = Vec::new();
+        loop {
+            flashes.push(octopi.step());
+            let steps = flashes.len();
+            if let Some(&start) = seen.get(&octopi.energy) {
+                return Cycle {
+                    start,
+                    length: steps - start,
+                    flashes,
+                    octopuses: octopi.len(),
+                };
+            }
+            seen.insert(octopi.energy.clone(), steps);
+        }
+    }
+
+    // Total flashes over the first `steps` steps, however many there are
+    fn total_flashes(&self, steps: u64) -> u128 {
+        let sum = |flashes: &[usize]| flashes.iter().map(|n| *n as u128).sum::<u128>();
+        let simulated = self.flashes.len() as u64;
+        if steps <= simulated {
+            return sum(&self.flashes[..steps as usize]);
         }
 
-        if grid
+        let cycle = &self.flashes[self.start..];
+        let repeats = (steps - self.start as u64) / self.length as u64;
+        let remainder = ((steps - self.start as u64) % self.length as u64) as usize;
+        sum(&self.flashes[..self.start]) + repeats as u128 * sum(cycle) + sum(&cycle[..remainder])
+    }
+
+    // The first step where every octopus flashes. Every state the grid will
+    // ever be in has been seen, so if it hasn't happened yet it never will.
+    fn first_sync(&self) -> Option<usize> {
+        self.flashes
             .iter()
-            .filter(|&(_coord, octopus)| *octopus == 0)
-            .count()
-            == grid.len()
-        {
-            println!("p2 {}", step_count + 1);
-            break;
+            .position(|flashed| *flashed == self.octopuses)
+            .map(|i| i + 1)
+    }
+}
+
+fn main() -> Result<()> {
+    let text = read_text("11/input.txt")?;
+
+    // --torus wraps the edges, --four leaves out the diagonals and
+    // --threshold N makes octopuses flash past N instead of 9. --steps N
+    // counts the flashes over N steps from the start, for any N.
+    let mut rules = Rules::default();
+    let mut steps = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut number = || {
+            args.next()
+                .and_then(|number| number.parse::<u64>().ok())
+                .unwrap_or_else(|| panic!("{} needs a number", arg))
+        };
+        match arg.as_str() {
+            "--torus" => rules.edges = Edges::Torus,
+            "--four" => rules.neighbourhood = Neighbourhood::Four,
+            "--threshold" => rules.flash_threshold = number() as u32,
+            "--steps" => steps.push(number()),
+            _ => panic!("Unknown argument {}", arg),
         }
+    }
 
-        step_count += 1;
+    let cycle = Cycle::find(Octopi::parse(&text, &rules));
+
+    println!("p1 {}", cycle.total_flashes(100));
+    match cycle.first_sync() {
+        Some(step) => println!("p2 {}", step),
+        None => println!("p2 never"),
+    }
+    println!(
+        "the grid repeats every {} steps from step {}",
+        cycle.length, cycle.start
+    );
+
+    for steps in steps {
+        println!(
+            "{} flashes after {} steps",
+            cycle.total_flashes(steps),
+            steps
+        );
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "5483143223
+2745854711
+5264556173
+6141336146
+6357385478
+4167524645
+2176841721
+6882881134
+4846848554
+5283751526";
+
+    #[test]
+    fn test_flashes() {
+        let mut octopi = Octopi::parse(EXAMPLE, &Rules::default());
+        let flashes: Vec<usize> = (0..100).map(|_| octopi.step()).collect();
+        assert_eq!(flashes[..2], [0, 35]);
+        assert_eq!(flashes[..10].iter().sum::<usize>(), 204);
+        assert_eq!(flashes.iter().sum::<usize>(), 1656);
+
+        let cycle = Cycle::find(Octopi::parse(EXAMPLE, &Rules::default()));
+        assert_eq!(cycle.total_flashes(10), 204);
+        assert_eq!(cycle.total_flashes(100), 1656);
+    }
+
+    #[test]
+    fn test_sync() {
+        let mut octopi = Octopi::parse(EXAMPLE, &Rules::default());
+        let sync = (1..).find(|_| octopi.step() == octopi.len());
+        assert_eq!(sync, Some(195));
+        assert!(octopi.energy.iter().all(|energy| *energy == 0));
+    }
+
+    #[test]
+    fn test_variants() {
+        // a torus has no edges, so every octopus has eight neighbours
+        let torus = Rules {
+            edges: Edges::Torus,
+            ..Rules::default()
+        };
+        let octopi = Octopi::parse(EXAMPLE, &torus);
+        assert_eq!(octopi.neighbours.len(), 8 * octopi.len());
+        let four = Rules {
+            edges: Edges::Torus,
+            neighbourhood: Neighbourhood::Four,
+            ..Rules::default()
+        };
+        assert_eq!(Octopi::parse(EXAMPLE, &four).neighbours.len(), 4 * 100);
+
+        // a 2x2 torus has every octopus next to every other one, once
+        let mut octopi = Octopi::parse("90\n00", &torus);
+        assert_eq!(octopi.neighbours.len(), 4 * 3);
+        assert_eq!(octopi.step(), 1);
+        assert_eq!(octopi.energy, vec![0, 2, 2, 2]);
+
+        // with four neighbours the flash only reaches the sides
+        let four = Rules {
+            neighbourhood: Neighbourhood::Four,
+            ..Rules::default()
+        };
+        let mut octopi = Octopi::parse("000\n090\n000", &four);
+        assert_eq!(octopi.step(), 1);
+        assert_eq!(octopi.energy, vec![1, 2, 1, 2, 0, 2, 1, 2, 1]);
+    }
+
+    #[test]
+    fn test_threshold_and_sync() {
+        // starting energies above a low threshold still flash
+        let low = Rules {
+            flash_threshold: 2,
+            ..Rules::default()
+        };
+        let mut octopi = Octopi::parse("90\n00", &low);
+        assert_eq!(octopi.step(), 1);
+        assert_eq!(octopi.energy, vec![0, 2, 2, 2]);
+
+        for (rules, expected) in [
+            (Rules::default(), 195),
+            (
+                Rules {
+                    edges: Edges::Torus,
+                    ..Rules::default()
+                },
+                29,
+            ),
+            (
+                Rules {
+                    neighbourhood: Neighbourhood::Four,
+                    flash_threshold: 5,
+                    ..Rules::default()
+                },
+                31,
+            ),
+        ] {
+            let mut octopi = Octopi::parse(EXAMPLE, &rules);
+            let sync = (1..10_000).find(|_| octopi.step() == octopi.len());
+            assert_eq!(sync, Some(expected), "{:?}", rules);
+            assert!(octopi.energy.iter().all(|energy| *energy == 0));
+        }
+    }
+
+    #[test]
+    fn test_cycle() {
+        let cycle = Cycle::find(Octopi::parse(EXAMPLE, &Rules::default()));
+        // once in sync every octopus counts up from 0 together
+        assert_eq!(cycle.first_sync(), Some(195));
+        assert_eq!(cycle.length, 10);
+        assert!(cycle.start <= 195);
+
+        // the prediction matches stepping it out
+        let mut octopi = Octopi::parse(EXAMPLE, &Rules::default());
+        let mut total = 0;
+        for steps in 1..=1000 {
+            total += octopi.step() as u128;
+            assert_eq!(cycle.total_flashes(steps), total);
+        }
+        // all 100 flash once every 10 steps after syncing at step 195
+        let after_sync = cycle.total_flashes(195);
+        assert_eq!(
+            cycle.total_flashes(1_000_000_000_195),
+            after_sync + 100 * 100_000_000_000
+        );
+    }
+
+    #[test]
+    fn test_never_syncs() {
+        // a row whose flashes never line up
+        let four = Rules {
+            neighbourhood: Neighbourhood::Four,
+            ..Rules::default()
+        };
+        let cycle = Cycle::find(Octopi::parse("0905", &four));
+        assert_eq!(cycle.first_sync(), None);
+        assert_eq!(cycle.total_flashes(0), 0);
+        assert!(cycle.total_flashes(1_000_000) > 0);
+    }
+}
@@ -3,85 +3,610 @@ use std::io::Result;
 
 use read_input::read_text;
 
-fn add_connection(connections: &mut HashMap<String, Vec<String>>, key: &str, value: &str) {
-    if !connections.contains_key(key) {
-        connections.insert(key.to_string(), Vec::new());
+// Small caves are tracked as bits in a u64
+const MAX_SMALL_CAVES: usize = 64;
+
+// Which paths through the caves count. Small caves can be visited once,
+// plus `revisits` extra visits to small caves shared between all of them.
+// Start is never gone back to and a path stops at end.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct VisitPolicy {
+    revisits: usize,
+    // the most times a cave can be visited, on top of the rule above
+    limits: Vec<(String, usize)>,
+    // caves a path can't enter
+    forbidden: Vec<String>,
+    // caves every path has to pass through
+    required: Vec<String>,
+}
+
+// A policy with its caves looked up
+struct Walk {
+    limit: Vec<Option<usize>>,
+    forbidden: Vec<bool>,
+    // the bit a required cave sets in `State::reached`
+    required_bit: Vec<Option<u64>>,
+    // all the required bits
+    required: u64,
+    // where a limited cave's visits are counted in `State::visits`
+    counter: Vec<Option<usize>>,
+    counters: usize,
+    revisits: usize,
+}
+
+impl Walk {
+    // Checks stepping into `state.cave` against the policy, counting the visit
+    // if it's allowed. The small cave rules have already been applied.
+    fn enter(&self, mut state: State) -> Option<State> {
+        let cave = state.cave;
+        if self.forbidden[cave] {
+            return None;
+        }
+
+        if let Some(counter) = self.counter[cave] {
+            if state.visits[counter] >= self.limit[cave].unwrap() {
+                return None;
+            }
+            state.visits[counter] += 1;
+        }
+        if let Some(bit) = self.required_bit[cave] {
+            state.reached |= bit;
+        }
+
+        Some(state)
+    }
+
+    fn finished(&self, state: &State) -> bool {
+        state.reached == self.required
     }
-    connections.get_mut(key).unwrap().push(value.to_string());
 }
 
-fn traverse_paths(
-    path: Vec<String>,
-    connections: &HashMap<String, Vec<String>>,
-    can_visit_one_cave_twice: bool,
-    has_visited_a_cave_twice: bool,
-) -> Vec<Vec<String>> {
-    let next_connections = connections.get(path.last().unwrap()).unwrap();
-    let mut completed_paths = Vec::new();
-    for conn in next_connections {
-        if *conn == "end" {
-            let mut path = path.clone();
-            path.push(conn.to_owned());
-            completed_paths.push(path);
-        // is a small cave
-        } else if *conn == conn.to_lowercase() {
-            let has_visited_this_cave = path.contains(conn);
-            if *conn != "start"
-                && (!has_visited_this_cave
-                    || (can_visit_one_cave_twice && !has_visited_a_cave_twice))
-            {
-                let mut has_visited_a_cave_twice = has_visited_a_cave_twice;
-                if can_visit_one_cave_twice && has_visited_this_cave {
-                    has_visited_a_cave_twice = true;
+// Everything about a partial path that decides where it can go next
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct State {
+    cave: usize,
+    // small caves seen so far
+    visited: u64,
+    revisits: usize,
+    // visits to each limited cave
+    visits: Vec<usize>,
+    // required caves passed through
+    reached: u64,
+}
+
+// Caves are numbered in the order they first appear
+struct CaveSystem {
+    names: Vec<String>,
+    // the bit a small cave sets in a visited mask, None for big caves
+    small_bit: Vec<Option<u64>>,
+    connections: Vec<Vec<usize>>,
+    start: usize,
+    end: usize,
+}
+
+impl CaveSystem {
+    fn parse(text: &str) -> Self {
+        let mut ids: HashMap<String, usize> = HashMap::new();
+        let mut names = Vec::new();
+        let mut connections: Vec<Vec<usize>> = Vec::new();
+        let mut intern =
+            |name: &str, names: &mut Vec<String>, connections: &mut Vec<Vec<usize>>| {
+                *ids.entry(name.to_string()).or_insert_with(|| {
+                    names.push(name.to_string());
+                    connections.push(Vec::new());
+                    names.len() - 1
+                })
+            };
+        intern("start", &mut names, &mut connections);
+        intern("end", &mut names, &mut connections);
+
+        for line in text.lines() {
+            let pair = line.split('-').collect::<Vec<&str>>();
+            if pair.len() != 2 {
+                panic!("Could not parse line {} correctly", line);
+            }
+            let a = intern(pair[0], &mut names, &mut connections);
+            let b = intern(pair[1], &mut names, &mut connections);
+            connections[a].push(b);
+            connections[b].push(a);
+        }
+
+        let mut small_caves = 0;
+        let small_bit: Vec<Option<u64>> = names
+            .iter()
+            .map(|name| {
+                if *name != name.to_lowercase() {
+                    return None;
+                }
+                small_caves += 1;
+                if small_caves > MAX_SMALL_CAVES {
+                    panic!("More than {} small caves", MAX_SMALL_CAVES);
                 }
-                let mut path = path.clone();
-                path.push(conn.clone());
-                let mut result = traverse_paths(
-                    path,
-                    connections,
-                    can_visit_one_cave_twice,
-                    has_visited_a_cave_twice,
+                Some(1 << (small_caves - 1))
+            })
+            .collect();
+
+        CaveSystem {
+            names,
+            small_bit,
+            connections,
+            start: 0,
+            end: 1,
+        }
+    }
+
+    fn id(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|other| other == name)
+    }
+
+    // None when the policy requires a cave that isn't there
+    fn resolve(&self, policy: &VisitPolicy) -> Option<Walk> {
+        let mut limit = vec![None; self.names.len()];
+        for (name, most) in &policy.limits {
+            if let Some(cave) = self.id(name) {
+                limit[cave] = Some(*most);
+            }
+        }
+        let mut forbidden = vec![false; self.names.len()];
+        for cave in policy.forbidden.iter().filter_map(|name| self.id(name)) {
+            forbidden[cave] = true;
+        }
+
+        // two big caves next to each other could be walked between forever,
+        // unless a limit on either one or forbidding it stops that
+        let unbounded = |cave: usize| {
+            self.small_bit[cave].is_none() && limit[cave].is_none() && !forbidden[cave]
+        };
+        for (cave, next) in self.connections.iter().enumerate() {
+            if let Some(other) = next
+                .iter()
+                .find(|other| unbounded(cave) && unbounded(**other))
+            {
+                panic!(
+                    "Big caves {} and {} connect, so there are infinitely many paths",
+                    self.names[cave], self.names[*other]
                 );
-                completed_paths.append(&mut result);
             }
-        } else {
-            let mut path = path.clone();
-            path.push(conn.clone());
-            let mut result = traverse_paths(
-                path,
-                connections,
-                can_visit_one_cave_twice,
-                has_visited_a_cave_twice,
-            );
-            completed_paths.append(&mut result);
+        }
+
+        let mut required_bit = vec![None; self.names.len()];
+        let mut required = 0;
+        let mut bits = 0;
+        for name in &policy.required {
+            let cave = self.id(name)?;
+            // naming a cave twice doesn't make it any more required
+            if required_bit[cave].is_some() {
+                continue;
+            }
+            if bits >= MAX_SMALL_CAVES {
+                panic!("More than {} required caves", MAX_SMALL_CAVES);
+            }
+            required_bit[cave] = Some(1 << bits);
+            required |= 1 << bits;
+            bits += 1;
+        }
+
+        // only limited caves need their visits counted
+        let mut counter = vec![None; self.names.len()];
+        let mut counters = 0;
+        for cave in 0..self.names.len() {
+            if limit[cave].is_some() {
+                counter[cave] = Some(counters);
+                counters += 1;
+            }
+        }
+
+        Some(Walk {
+            limit,
+            forbidden,
+            required_bit,
+            required,
+            counter,
+            counters,
+            revisits: policy.revisits,
+        })
+    }
+
+    // Where a path is before it takes its first step
+    fn first_state(&self, walk: &Walk) -> Option<State> {
+        let state = State {
+            cave: self.start,
+            visited: self.small_bit[self.start].unwrap_or(0),
+            revisits: walk.revisits,
+            visits: vec![0; walk.counters],
+            reached: 0,
+        };
+        walk.enter(state)
+    }
+
+    // Where a path can go next from `state`. Start is never gone back to and
+    // end is where a path stops, so it's left to the caller.
+    fn moves(&self, walk: &Walk, state: &State) -> Vec<State> {
+        self.connections[state.cave]
+            .iter()
+            .filter(|next| **next != self.start)
+            .filter_map(|&next| {
+                let mut next_state = match self.small_bit[next] {
+                    Some(bit) if state.visited & bit != 0 => {
+                        if state.revisits == 0 {
+                            return None;
+                        }
+                        State {
+                            revisits: state.revisits - 1,
+                            ..state.clone()
+                        }
+                    }
+                    Some(bit) => State {
+                        visited: state.visited | bit,
+                        ..state.clone()
+                    },
+                    None => state.clone(),
+                };
+                next_state.cave = next;
+                walk.enter(next_state)
+            })
+            .collect()
+    }
+
+    // Counts the paths from start to end the policy allows. Paths on from a
+    // cave only depend on the state they're in there, so each state is
+    // counted once however many ways lead to it.
+    fn count_paths(&self, policy: &VisitPolicy) -> u64 {
+        let Some(walk) = self.resolve(policy) else {
+            return 0;
+        };
+        match self.first_state(&walk) {
+            Some(state) => self.count_from(&walk, state, &mut HashMap::new()),
+            None => 0,
+        }
+    }
+
+    fn count_from(&self, walk: &Walk, state: State, memo: &mut HashMap<State, u64>) -> u64 {
+        if state.cave == self.end {
+            return walk.finished(&state) as u64;
+        }
+        if let Some(count) = memo.get(&state) {
+            return *count;
+        }
+
+        let count = self
+            .moves(walk, &state)
+            .into_iter()
+            .map(|next| self.count_from(walk, next, memo))
+            .sum();
+        memo.insert(state, count);
+        count
+    }
+
+    // Lists the same paths `count_paths` counts, as cave ids
+    fn paths(&self, policy: &VisitPolicy) -> Vec<Vec<usize>> {
+        let mut path = vec![self.start];
+        let mut paths = Vec::new();
+        if let Some(walk) = self.resolve(policy) {
+            if let Some(state) = self.first_state(&walk) {
+                self.extend(&walk, state, &mut path, &mut paths);
+            }
+        }
+        paths
+    }
+
+    fn extend(
+        &self,
+        walk: &Walk,
+        state: State,
+        path: &mut Vec<usize>,
+        paths: &mut Vec<Vec<usize>>,
+    ) {
+        if state.cave == self.end {
+            if walk.finished(&state) {
+                paths.push(path.clone());
+            }
+            return;
+        }
+
+        for next in self.moves(walk, &state) {
+            path.push(next.cave);
+            self.extend(walk, next, path, paths);
+            path.pop();
         }
     }
 
-    completed_paths
+    fn path_to_string(&self, path: &[usize]) -> String {
+        path.iter()
+            .map(|cave| self.names[*cave].as_str())
+            .collect::<Vec<&str>>()
+            .join(",")
+    }
 }
 
 fn main() -> Result<()> {
     let text = read_text("12/input.txt")?;
 
-    let mut connections = HashMap::new();
+    let caves = CaveSystem::parse(&text);
+
+    let mut policy = VisitPolicy::default();
+    println!("{}", caves.count_paths(&policy));
+    policy.revisits = 1;
+    println!("{}", caves.count_paths(&policy));
+
+    // --revisits K allows K extra small cave visits, --limit NAME=N at most N
+    // visits to a cave, --forbid NAME keeps paths out of a cave and --require
+    // NAME makes them pass through it. The paths these allow are counted, and
+    // --list prints them too.
+    let mut policy = VisitPolicy::default();
+    let mut custom = false;
+    let mut list = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            custom = true;
+            args.next()
+                .unwrap_or_else(|| panic!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--revisits" => {
+                policy.revisits = value().parse::<usize>().expect("--revisits needs a number")
+            }
+            "--limit" => {
+                let limit = value();
+                let (name, most) = limit.split_once('=').expect("--limit needs NAME=N");
+                policy.limits.push((
+                    name.to_string(),
+                    most.parse::<usize>().expect("--limit needs NAME=N"),
+                ));
+            }
+            "--forbid" => policy.forbidden.push(value()),
+            "--require" => policy.required.push(value()),
+            "--list" => list = true,
+            _ => panic!("Unknown argument {}", arg),
+        }
+    }
+
+    if custom {
+        println!("{}", caves.count_paths(&policy));
+    }
+    if list {
+        for path in caves.paths(&policy) {
+            println!("{}", caves.path_to_string(&path));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMALL: &str = "start-A
+start-b
+A-c
+A-b
+b-d
+A-end
+b-end";
+
+    const LARGER: &str = "fs-end
+he-DX
+fs-he
+start-DX
+pj-DX
+end-zg
+zg-sl
+zg-pj
+pj-he
+RW-he
+fs-DX
+pj-RW
+zg-RW
+start-pj
+he-WI
+zg-he
+pj-fs
+start-RW";
 
-    for line in text.lines() {
-        let pair = line.split("-").collect::<Vec<&str>>();
-        if pair.len() != 2 {
-            panic!("Could not parse line {} correctly", line);
+    fn revisits(k: usize) -> VisitPolicy {
+        VisitPolicy {
+            revisits: k,
+            ..VisitPolicy::default()
         }
-        add_connection(&mut connections, pair[0], pair[1]);
-        add_connection(&mut connections, pair[1], pair[0]);
     }
 
-    let paths = vec!["start".to_string()];
+    fn path_strings(caves: &CaveSystem, policy: &VisitPolicy) -> Vec<String> {
+        caves
+            .paths(policy)
+            .iter()
+            .map(|path| caves.path_to_string(path))
+            .collect()
+    }
 
-    let resulting_paths = traverse_paths(paths, &connections, false, false);
-    println!("{}", resulting_paths.len());
+    #[test]
+    fn test_count_paths() {
+        let once = VisitPolicy::default();
+        let twice = revisits(1);
 
-    let paths = vec!["start".to_string()];
-    let resulting_paths = traverse_paths(paths, &connections, true, false);
-    println!("{}", resulting_paths.len());
+        let caves = CaveSystem::parse(SMALL);
+        assert_eq!(caves.count_paths(&once), 10);
+        assert_eq!(caves.count_paths(&twice), 36);
 
-    Ok(())
+        let caves = CaveSystem::parse(LARGER);
+        assert_eq!(caves.count_paths(&once), 226);
+        assert_eq!(caves.count_paths(&twice), 3509);
+    }
+
+    #[test]
+    fn test_paths_match_counts() {
+        let caves = CaveSystem::parse(SMALL);
+        for k in 0..4 {
+            let paths = caves.paths(&revisits(k));
+            assert_eq!(paths.len() as u64, caves.count_paths(&revisits(k)));
+        }
+        let paths: Vec<String> = caves
+            .paths(&VisitPolicy::default())
+            .iter()
+            .map(|path| caves.path_to_string(path))
+            .collect();
+        assert!(paths.contains(&"start,A,b,A,c,A,end".to_string()));
+        assert!(paths.contains(&"start,b,end".to_string()));
+    }
+
+    // A chain of small caves s0 to s{n - 1}, each joined to the next through
+    // two big caves, so there are 2^(n - 1) paths
+    fn chain(small_caves: usize) -> String {
+        let mut text = String::from("start-s0\n");
+        for i in 0..small_caves - 1 {
+            for big in ["B", "C"] {
+                text += &format!("s{}-{}{}\ns{}-{}{}\n", i, big, i, i + 1, big, i);
+            }
+        }
+        text + &format!("s{}-end", small_caves - 1)
+    }
+
+    #[test]
+    fn test_many_small_caves() {
+        // 62 in the chain plus start and end
+        let caves = CaveSystem::parse(&chain(62));
+        assert_eq!(caves.count_paths(&VisitPolicy::default()), 1 << 61);
+    }
+
+    #[test]
+    #[should_panic(expected = "More than 64 small caves")]
+    fn test_too_many_small_caves() {
+        CaveSystem::parse(&chain(63));
+    }
+
+    #[test]
+    #[should_panic(expected = "Big caves A and B connect")]
+    fn test_adjacent_big_caves() {
+        let caves = CaveSystem::parse("start-A\nA-B\nB-end");
+        caves.count_paths(&VisitPolicy::default());
+    }
+
+    #[test]
+    fn test_limited_adjacent_big_caves() {
+        let caves = CaveSystem::parse("start-A\nA-B\nB-end\nA-end");
+        // B at most twice bounds the walking back and forth between A and B
+        let policy = VisitPolicy {
+            limits: vec![("B".to_string(), 2)],
+            ..VisitPolicy::default()
+        };
+        let mut paths = path_strings(&caves, &policy);
+        paths.sort();
+        assert_eq!(
+            paths,
+            [
+                "start,A,B,A,B,A,end",
+                "start,A,B,A,B,end",
+                "start,A,B,A,end",
+                "start,A,B,end",
+                "start,A,end",
+            ]
+        );
+        assert_eq!(caves.count_paths(&policy), 5);
+
+        let forbidden = VisitPolicy {
+            forbidden: vec!["B".to_string()],
+            ..VisitPolicy::default()
+        };
+        assert_eq!(caves.count_paths(&forbidden), 1);
+    }
+
+    #[test]
+    fn test_policies() {
+        let caves = CaveSystem::parse(SMALL);
+
+        // never through c or d
+        let policy = VisitPolicy {
+            revisits: 1,
+            forbidden: vec!["c".to_string(), "d".to_string()],
+            ..VisitPolicy::default()
+        };
+        let paths = path_strings(&caves, &policy);
+        assert!(paths
+            .iter()
+            .all(|path| path.split(',').all(|cave| cave != "c" && cave != "d")));
+        assert_eq!(paths.len() as u64, caves.count_paths(&policy));
+
+        // through c on every path
+        let policy = VisitPolicy {
+            required: vec!["c".to_string()],
+            ..VisitPolicy::default()
+        };
+        let paths = path_strings(&caves, &policy);
+        assert!(paths.iter().all(|path| path.contains(",c,")));
+        assert_eq!(paths.len(), 5);
+        assert_eq!(caves.count_paths(&policy), 5);
+        let twice = VisitPolicy {
+            required: vec!["c".to_string(), "c".to_string()],
+            ..VisitPolicy::default()
+        };
+        assert_eq!(caves.count_paths(&twice), 5);
+        assert_eq!(path_strings(&caves, &twice), paths);
+
+        // A at most twice, even though it's big
+        let policy = VisitPolicy {
+            limits: vec![("A".to_string(), 2)],
+            ..VisitPolicy::default()
+        };
+        let paths = path_strings(&caves, &policy);
+        assert!(paths.iter().all(|path| path.matches('A').count() <= 2));
+        assert_eq!(paths.len() as u64, caves.count_paths(&policy));
+        assert!(paths.len() < 10);
+
+        // b can be revisited twice but no other small cave can be revisited
+        let policy = VisitPolicy {
+            revisits: 3,
+            limits: vec![
+                ("b".to_string(), 3),
+                ("c".to_string(), 1),
+                ("d".to_string(), 1),
+            ],
+            ..VisitPolicy::default()
+        };
+        let paths = path_strings(&caves, &policy);
+        assert!(paths.contains(&"start,b,A,b,A,b,end".to_string()));
+        assert!(paths.iter().all(|path| path.matches('b').count() <= 3));
+        assert_eq!(paths.len() as u64, caves.count_paths(&policy));
+
+        // nothing gets through a required cave that doesn't exist, or out of
+        // a forbidden start
+        let missing = VisitPolicy {
+            required: vec!["zz".to_string()],
+            ..VisitPolicy::default()
+        };
+        assert_eq!(caves.count_paths(&missing), 0);
+        let blocked = VisitPolicy {
+            forbidden: vec!["start".to_string()],
+            ..VisitPolicy::default()
+        };
+        assert!(caves.paths(&blocked).is_empty());
+    }
+
+    #[test]
+    fn test_counts_match_brute_force() {
+        // every combination of the policy's parts, checked against the
+        // listed paths
+        let caves = CaveSystem::parse(LARGER);
+        for k in 0..3 {
+            for forbidden in [vec![], vec!["sl".to_string()], vec!["he".to_string()]] {
+                for required in [vec![], vec!["zg".to_string(), "DX".to_string()]] {
+                    for limits in [vec![], vec![("pj".to_string(), 1), ("RW".to_string(), 2)]] {
+                        let policy = VisitPolicy {
+                            revisits: k,
+                            limits: limits.clone(),
+                            forbidden: forbidden.clone(),
+                            required: required.clone(),
+                        };
+                        let paths = caves.paths(&policy);
+                        assert_eq!(
+                            paths.len() as u64,
+                            caves.count_paths(&policy),
+                            "{:?}",
+                            policy
+                        );
+                    }
+                }
+            }
+        }
+    }
 }
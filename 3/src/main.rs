@@ -1,17 +1,62 @@
-use core::num;
 use std::cmp::Ordering;
+use std::fmt;
 use std::io::Result;
 
 use read_input::read_text;
 
-fn bin_vec_to_num(vec: &Vec<u32>) -> u32 {
+// How to pick the most common bit when a column has as many 1s as 0s.
+// The least common bit is always the complement, so one policy covers
+// gamma/epsilon as well as the O2 and CO2 ratings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TiePolicy {
+    PreferOne,
+    PreferZero,
+    Error,
+}
+
+impl TiePolicy {
+    fn from_arg(arg: &str) -> Option<TiePolicy> {
+        match arg {
+            "prefer-one" => Some(TiePolicy::PreferOne),
+            "prefer-zero" => Some(TiePolicy::PreferZero),
+            "error" => Some(TiePolicy::Error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct TieError {
+    column: usize,
+}
+
+impl fmt::Display for TieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "column {} has as many 1s as 0s", self.column)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Rates {
+    gamma: Vec<u32>,
+    epsilon: Vec<u32>,
+    tied_columns: Vec<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Rating {
+    value: Vec<u32>,
+    tied_columns: Vec<usize>,
+}
+
+fn bin_vec_to_num(vec: &[u32]) -> u32 {
     vec.iter()
         .rev()
         .enumerate()
         .fold(0, |sum, (i, value)| sum + (2u32.pow(i as u32) * *value))
 }
 
-fn sum_full_list(binary_values: &Vec<Vec<u32>>, size: usize) -> Vec<u32> {
+fn sum_full_list(binary_values: &[Vec<u32>], size: usize) -> Vec<u32> {
     binary_values
         .iter()
         .fold(vec![0; size], |mut sum_vec, row| {
@@ -23,95 +68,212 @@ fn sum_full_list(binary_values: &Vec<Vec<u32>>, size: usize) -> Vec<u32> {
         })
 }
 
-fn calculate_rates(sum_vec: &Vec<u32>, size: usize, num_of_entries: u32) -> (Vec<u32>, Vec<u32>) {
-    let mut gamma_rate = vec![0; size];
-    let mut epsilon_rate = vec![0; size];
+// Compares the number of 1s in a column against the number of 0s, without
+// the rounding that halving the entry count would introduce.
+fn majority(ones: u32, num_of_entries: usize) -> Ordering {
+    (ones as usize * 2).cmp(&num_of_entries)
+}
+
+// Returns the most common bit for a column and whether the column was a tie.
+fn most_common_bit(
+    ones: u32,
+    num_of_entries: usize,
+    column: usize,
+    policy: TiePolicy,
+) -> std::result::Result<(u32, bool), TieError> {
+    match majority(ones, num_of_entries) {
+        Ordering::Greater => Ok((1, false)),
+        Ordering::Less => Ok((0, false)),
+        Ordering::Equal => match policy {
+            TiePolicy::PreferOne => Ok((1, true)),
+            TiePolicy::PreferZero => Ok((0, true)),
+            TiePolicy::Error => Err(TieError { column }),
+        },
+    }
+}
+
+fn calculate_rates(
+    sum_vec: &[u32],
+    num_of_entries: usize,
+    policy: TiePolicy,
+) -> std::result::Result<Rates, TieError> {
+    let mut rates = Rates {
+        gamma: Vec::with_capacity(sum_vec.len()),
+        epsilon: Vec::with_capacity(sum_vec.len()),
+        tied_columns: Vec::new(),
+    };
 
     for (i, v) in sum_vec.iter().enumerate() {
-        if *v > num_of_entries / 2 {
-            gamma_rate[i] = 1;
-            epsilon_rate[i] = 0;
-        } else {
-            gamma_rate[i] = 0;
-            epsilon_rate[i] = 1;
+        let (bit, tied) = most_common_bit(*v, num_of_entries, i, policy)?;
+        rates.gamma.push(bit);
+        rates.epsilon.push(1 - bit);
+        if tied {
+            rates.tied_columns.push(i);
         }
     }
 
-    (gamma_rate, epsilon_rate)
+    Ok(rates)
 }
 
-fn get_bits_ordering(
-    binary_values: &Vec<Vec<u32>>,
-    index: usize,
-    num_of_entries: usize,
-) -> Ordering {
-    let sum = binary_values
-        .iter()
-        .fold(0, |sum, binary| sum + binary[index]);
+// Filters the list column by column, keeping either the most or the least
+// common bit, until one entry remains. A column where every entry has the same
+// bit has no least common bit to keep, so it filters nothing out.
+fn find_rating(
+    binary_values: &[Vec<u32>],
+    keep_most_common: bool,
+    policy: TiePolicy,
+) -> std::result::Result<Rating, TieError> {
+    let mut list = binary_values.to_vec();
+    let mut tied_columns = Vec::new();
+    let num_of_binary_digits = list[0].len();
+
+    for i in 0..num_of_binary_digits {
+        if list.len() <= 1 {
+            break;
+        }
+
+        let ones = list.iter().fold(0, |sum, binary| sum + binary[i]);
+        let (most_common, tied) = most_common_bit(ones, list.len(), i, policy)?;
+        if tied {
+            tied_columns.push(i);
+        }
 
-    (sum * 2).cmp(&(num_of_entries as u32))
+        let keep = if keep_most_common {
+            most_common
+        } else {
+            1 - most_common
+        };
+        if list.iter().any(|bin| bin[i] == keep) {
+            list.retain(|bin| bin[i] == keep);
+        }
+    }
+
+    Ok(Rating {
+        value: list.swap_remove(0),
+        tied_columns,
+    })
 }
 
 fn main() -> Result<()> {
     let text = read_text("3/input.txt")?;
 
-    let count = text.lines().count();
+    let binary_values: Vec<Vec<u32>> = text
+        .lines()
+        .map(|line| line.chars().map(|v| v.to_digit(10).unwrap()).collect())
+        .collect();
 
-    let mut binary_values: Vec<Vec<u32>> = Vec::with_capacity(count);
-    let count = count as u32;
-    for line in text.lines() {
-        let binary = line.chars().map(|v| v.to_digit(10).unwrap()).collect();
-        binary_values.push(binary);
-    }
+    let policy = match std::env::args().nth(1) {
+        Some(arg) => {
+            TiePolicy::from_arg(&arg).expect("tie policy must be prefer-one, prefer-zero or error")
+        }
+        None => TiePolicy::PreferOne,
+    };
 
     let num_of_binary_digits = binary_values[0].len();
     let sum_vec = sum_full_list(&binary_values, num_of_binary_digits);
 
-    let (gamma_rate, epsilon_rate) = calculate_rates(&sum_vec, num_of_binary_digits, count);
+    let rates = match calculate_rates(&sum_vec, binary_values.len(), policy) {
+        Ok(rates) => rates,
+        Err(err) => {
+            println!("{}", err);
+            return Ok(());
+        }
+    };
 
-    let gamma_num = bin_vec_to_num(&gamma_rate);
-    let epsilon_num = bin_vec_to_num(&epsilon_rate);
+    let gamma_num = bin_vec_to_num(&rates.gamma);
+    let epsilon_num = bin_vec_to_num(&rates.epsilon);
     let consumption = gamma_num * epsilon_num;
 
     println!("{}", consumption);
+    if !rates.tied_columns.is_empty() {
+        println!("tied columns: {:?}", rates.tied_columns);
+    }
 
-    let mut o2_list = binary_values;
-    let mut co2_list = o2_list.clone();
-
-    while o2_list.len() > 1 && co2_list.len() > 1 {
-        for i in 0..num_of_binary_digits {
-            if o2_list.len() > 1 {
-                match get_bits_ordering(&o2_list, i, o2_list.len()) {
-                    Ordering::Less => {
-                        // if there are fewer 1s, keep the 0s
-                        o2_list = o2_list.iter().filter(|bin| bin[i] == 0).cloned().collect();
-                    }
-                    _ => {
-                        // if there are more or equal 1s, keep the 1s
-                        o2_list = o2_list.iter().filter(|bin| bin[i] == 1).cloned().collect();
-                    }
-                }
-            }
-
-            if co2_list.len() > 1 {
-                match get_bits_ordering(&co2_list, i, co2_list.len()) {
-                    Ordering::Less => {
-                        // if 1s are less common keep them
-                        co2_list = co2_list.iter().filter(|bin| bin[i] == 1).cloned().collect();
-                    }
-                    _ => {
-                        // if 0s are less common or it is even, keep the 0s
-                        co2_list = co2_list.iter().filter(|bin| bin[i] == 0).cloned().collect();
-                    }
-                }
-            }
+    let (o2_rating, co2_rating) = match (
+        find_rating(&binary_values, true, policy),
+        find_rating(&binary_values, false, policy),
+    ) {
+        (Ok(o2_rating), Ok(co2_rating)) => (o2_rating, co2_rating),
+        (Err(err), _) | (_, Err(err)) => {
+            println!("{}", err);
+            return Ok(());
         }
-    }
+    };
 
-    let o2 = bin_vec_to_num(&o2_list[0]);
-    let co2 = bin_vec_to_num(&co2_list[0]);
+    let o2 = bin_vec_to_num(&o2_rating.value);
+    let co2 = bin_vec_to_num(&co2_rating.value);
 
     println!("{} * {} = {}", o2, co2, o2 * co2);
+    println!(
+        "o2 tied columns: {:?}, co2 tied columns: {:?}",
+        o2_rating.tied_columns, co2_rating.tied_columns
+    );
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Vec<Vec<u32>> {
+        [
+            "00100", "11110", "10110", "10111", "10101", "01111", "00111", "11100", "10000",
+            "11001", "00010", "01010",
+        ]
+        .iter()
+        .map(|line| line.chars().map(|v| v.to_digit(10).unwrap()).collect())
+        .collect()
+    }
+
+    #[test]
+    fn test_calculate_rates() {
+        let values = example();
+        let sum_vec = sum_full_list(&values, 5);
+        let rates = calculate_rates(&sum_vec, values.len(), TiePolicy::PreferOne).unwrap();
+        assert_eq!(bin_vec_to_num(&rates.gamma), 22);
+        assert_eq!(bin_vec_to_num(&rates.epsilon), 9);
+        assert!(rates.tied_columns.is_empty());
+    }
+
+    #[test]
+    fn test_calculate_rates_ties() {
+        // an odd count must not round: 2 of 5 is not a majority
+        let rates = calculate_rates(&[2, 3], 5, TiePolicy::PreferOne).unwrap();
+        assert_eq!(rates.gamma, vec![0, 1]);
+
+        let rates = calculate_rates(&[2, 3], 4, TiePolicy::PreferZero).unwrap();
+        assert_eq!(rates.gamma, vec![0, 1]);
+        assert_eq!(rates.epsilon, vec![1, 0]);
+        assert_eq!(rates.tied_columns, vec![0]);
+
+        assert_eq!(
+            calculate_rates(&[3, 2], 4, TiePolicy::Error),
+            Err(TieError { column: 1 })
+        );
+    }
+
+    #[test]
+    fn test_find_rating() {
+        let values = example();
+        let o2 = find_rating(&values, true, TiePolicy::PreferOne).unwrap();
+        let co2 = find_rating(&values, false, TiePolicy::PreferOne).unwrap();
+        assert_eq!(bin_vec_to_num(&o2.value), 23);
+        assert_eq!(bin_vec_to_num(&co2.value), 10);
+        assert_eq!(o2.tied_columns, vec![4]);
+        assert_eq!(co2.tied_columns, vec![2]);
+
+        assert!(find_rating(&values, true, TiePolicy::Error).is_err());
+    }
+
+    #[test]
+    fn test_find_rating_shared_bit() {
+        // both entries start with 1, so CO2 can't keep the 0s there
+        let values = vec![vec![1, 0], vec![1, 1]];
+        let co2 = find_rating(&values, false, TiePolicy::PreferOne).unwrap();
+        assert_eq!(co2.value, vec![1, 0]);
+        assert_eq!(co2.tied_columns, vec![1]);
+        let o2 = find_rating(&values, true, TiePolicy::PreferOne).unwrap();
+        assert_eq!(o2.value, vec![1, 1]);
+    }
+}
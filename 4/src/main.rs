@@ -1,133 +1,587 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::fmt;
 use std::io::{self};
 use std::str::Lines;
 
 use read_input::read_text;
 use regex::Regex;
 
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum WinCondition {
+    // the board wins once it has completed this many lines
+    Lines(usize),
+    // every cell on the board has to be marked
+    Blackout,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Rules {
+    // (rows, cols) every board must have, or None to accept any rectangle
+    size: Option<(usize, usize)>,
+    diagonals: bool,
+    win: WinCondition,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            size: None,
+            diagonals: false,
+            win: WinCondition::Lines(1),
+        }
+    }
+}
+
+impl Rules {
+    fn from_args(args: &[String]) -> Self {
+        let mut rules = Rules::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--diagonals" => rules.diagonals = true,
+                "--blackout" => rules.win = WinCondition::Blackout,
+                "--lines" => {
+                    let lines = args.next().expect("--lines needs a count");
+                    rules.win = WinCondition::Lines(parse_num(lines) as usize);
+                }
+                "--size" => {
+                    let size = args.next().expect("--size needs ROWSxCOLS");
+                    let (rows, cols) = size.split_once('x').expect("--size needs ROWSxCOLS");
+                    rules.size = Some((parse_num(rows) as usize, parse_num(cols) as usize));
+                }
+                _ => panic!("Unknown argument {}", arg),
+            }
+        }
+
+        rules
+    }
+}
+
 struct Board {
-    called_numbers: HashSet<String>,
-    numbers: HashSet<String>,
-    grid: Vec<Vec<String>>,
+    grid: Vec<Vec<u32>>,
+    marked: Vec<Vec<bool>>,
+    // how many cells have been marked in each row, column and diagonal, so a
+    // win can be detected from the cell that was just marked
+    row_hits: Vec<usize>,
+    col_hits: Vec<usize>,
+    diagonal_hits: [usize; 2],
+    completed_lines: usize,
+    marked_count: usize,
+    unmarked_sum: u32,
+    has_won: bool,
 }
 
-fn parse_boards(lines: Lines) -> Vec<Board> {
-    let mut current_board: Option<Board> = None;
-    let mut boards: Vec<Board> = Vec::new();
-    let spaces_regex = Regex::new(r"\s+").unwrap();
-    for line in lines {
-        if line == "" {
-            if current_board.is_some() {
-                boards.push(current_board.unwrap().clone());
+impl Board {
+    fn new(grid: Vec<Vec<u32>>) -> Self {
+        let rows = grid.len();
+        let cols = grid[0].len();
+        if grid.iter().any(|row| row.len() != cols) {
+            panic!("Board rows have different lengths: {:?}", grid);
+        }
+        let unmarked_sum = grid.iter().flatten().sum();
+        Board {
+            marked: vec![vec![false; cols]; rows],
+            row_hits: vec![0; rows],
+            col_hits: vec![0; cols],
+            diagonal_hits: [0; 2],
+            completed_lines: 0,
+            marked_count: 0,
+            grid,
+            unmarked_sum,
+            has_won: false,
+        }
+    }
+
+    fn rows(&self) -> usize {
+        self.row_hits.len()
+    }
+
+    fn cols(&self) -> usize {
+        self.col_hits.len()
+    }
+
+    // Marks the cell and returns true if the board now meets the win condition
+    fn mark(&mut self, row: usize, col: usize, rules: &Rules) -> bool {
+        if self.marked[row][col] {
+            return false;
+        }
+        self.marked[row][col] = true;
+        self.marked_count += 1;
+        self.unmarked_sum -= self.grid[row][col];
+
+        self.row_hits[row] += 1;
+        if self.row_hits[row] == self.cols() {
+            self.completed_lines += 1;
+        }
+        self.col_hits[col] += 1;
+        if self.col_hits[col] == self.rows() {
+            self.completed_lines += 1;
+        }
+
+        if rules.diagonals {
+            // boards are square when diagonals are on, see Game::new
+            let size = self.rows();
+            if row == col {
+                self.diagonal_hits[0] += 1;
+                if self.diagonal_hits[0] == size {
+                    self.completed_lines += 1;
+                }
             }
-            current_board = None;
-        } else {
-            if current_board.is_none() {
-                current_board = Some(Board {
-                    called_numbers: HashSet::new(),
-                    numbers: HashSet::new(),
-                    grid: Vec::new(),
-                });
+            if row + col == size - 1 {
+                self.diagonal_hits[1] += 1;
+                if self.diagonal_hits[1] == size {
+                    self.completed_lines += 1;
+                }
             }
+        }
 
-            let board = current_board.as_mut().unwrap();
+        match rules.win {
+            WinCondition::Lines(needed) => self.completed_lines >= needed,
+            WinCondition::Blackout => self.marked_count == self.rows() * self.cols(),
+        }
+    }
 
-            let row_numbers: Vec<&str> = spaces_regex.split(line.trim()).collect();
-            board.grid.push(Vec::new());
-            for n in row_numbers {
-                board.numbers.insert(n.to_owned());
-                board.grid.last_mut().unwrap().push(n.to_owned());
-            }
+    fn score(&self, number: u32, rules: &Rules) -> u32 {
+        match rules.win {
+            WinCondition::Lines(_) => self.unmarked_sum * number,
+            // nothing is left unmarked on a full card, so score what was covered
+            WinCondition::Blackout => self.grid.iter().flatten().sum::<u32>() * number,
         }
     }
+}
 
-    boards
+struct Game {
+    boards: Vec<Board>,
+    rules: Rules,
+    // every number maps to each (board, row, col) it appears in
+    index: HashMap<u32, Vec<(usize, usize, usize)>>,
 }
 
-fn check_if_board_has_won(board: &Board) -> bool {
-    for row in &board.grid {
-        if row
-            .iter()
-            .filter(|col| board.called_numbers.contains(*col))
-            .count()
-            == board.grid.len()
-        {
-            return true;
+impl Game {
+    fn new(boards: Vec<Board>, rules: Rules) -> Self {
+        for board in &boards {
+            if let Some((rows, cols)) = rules.size {
+                if board.rows() != rows || board.cols() != cols {
+                    panic!(
+                        "Expected a {}x{} board but found {}x{}",
+                        rows,
+                        cols,
+                        board.rows(),
+                        board.cols()
+                    );
+                }
+            }
+            if rules.diagonals && board.rows() != board.cols() {
+                panic!("Diagonal lines need square boards");
+            }
+        }
+
+        let mut index: HashMap<u32, Vec<(usize, usize, usize)>> = HashMap::new();
+        for (b, board) in boards.iter().enumerate() {
+            for (row, numbers) in board.grid.iter().enumerate() {
+                for (col, n) in numbers.iter().enumerate() {
+                    index.entry(*n).or_default().push((b, row, col));
+                }
+            }
+        }
+
+        Game {
+            boards,
+            rules,
+            index,
         }
     }
 
-    // maybe inefficient as we go through the board twice
-    for col in 0..board.grid.len() {
-        if (0..board.grid.len())
-            .filter(|row| board.called_numbers.contains(&board.grid[*row][col]))
-            .count()
-            == board.grid.len()
-        {
-            return true;
+    // Calls a number, returning the boards that won on it in board order
+    fn call(&mut self, number: u32) -> Vec<usize> {
+        let mut winners = Vec::new();
+        if let Some(cells) = self.index.get(&number) {
+            for (b, row, col) in cells {
+                let board = &mut self.boards[*b];
+                if board.mark(*row, *col, &self.rules) && !board.has_won {
+                    board.has_won = true;
+                    winners.push(*b);
+                }
+            }
         }
+
+        winners
     }
 
-    false
+    fn score(&self, board: usize, number: u32) -> u32 {
+        self.boards[board].score(number, &self.rules)
+    }
 }
 
-fn parse_num(num: &str) -> i32 {
-    match num.parse::<i32>() {
+fn parse_boards(lines: Lines) -> Vec<Board> {
+    let mut boards: Vec<Board> = Vec::new();
+    let mut grid: Vec<Vec<u32>> = Vec::new();
+    let spaces_regex = Regex::new(r"\s+").unwrap();
+    for line in lines {
+        if line.is_empty() {
+            if !grid.is_empty() {
+                boards.push(Board::new(grid));
+            }
+            grid = Vec::new();
+        } else {
+            grid.push(spaces_regex.split(line.trim()).map(parse_num).collect());
+        }
+    }
+
+    if !grid.is_empty() {
+        boards.push(Board::new(grid));
+    }
+
+    boards
+}
+
+fn parse_num(num: &str) -> u32 {
+    match num.parse::<u32>() {
         Ok(n) => n,
         Err(_) => panic!("Could not parse {}", num),
     }
 }
 
+#[derive(Debug, PartialEq)]
+struct Win {
+    // index into the draw sequence, starting at 0
+    draw_index: usize,
+    number: u32,
+    score: u32,
+    // boards that win on the same draw share a rank, and the next rank skips
+    // past them, e.g. 1, 2, 2, 4
+    rank: usize,
+    tied: bool,
+}
+
+#[derive(Debug, PartialEq)]
+struct BoardResult {
+    board: usize,
+    win: Option<Win>,
+}
+
+// Every board in finishing order, followed by the boards that never won
+struct Timeline {
+    results: Vec<BoardResult>,
+}
+
+impl Timeline {
+    fn first(&self) -> Option<&Win> {
+        self.results.first().and_then(|result| result.win.as_ref())
+    }
+
+    fn last(&self) -> Option<&Win> {
+        self.results
+            .iter()
+            .rev()
+            .find_map(|result| result.win.as_ref())
+    }
+
+    fn to_json(&self) -> String {
+        let boards: Vec<String> = self
+            .results
+            .iter()
+            .map(|result| match &result.win {
+                Some(win) => format!(
+                    "{{\"board\":{},\"draw_index\":{},\"number\":{},\"score\":{},\"rank\":{},\"tied\":{}}}",
+                    result.board, win.draw_index, win.number, win.score, win.rank, win.tied
+                ),
+                None => format!(
+                    "{{\"board\":{},\"draw_index\":null,\"number\":null,\"score\":null,\"rank\":null,\"tied\":false}}",
+                    result.board
+                ),
+            })
+            .collect();
+
+        format!("{{\"boards\":[{}]}}", boards.join(","))
+    }
+}
+
+impl fmt::Display for Timeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>5} {:>6} {:>5} {:>7} {:>8}",
+            "rank", "board", "draw", "number", "score"
+        )?;
+        for result in &self.results {
+            match &result.win {
+                Some(win) => {
+                    let rank = if win.tied {
+                        format!("={}", win.rank)
+                    } else {
+                        win.rank.to_string()
+                    };
+                    writeln!(
+                        f,
+                        "{:>5} {:>6} {:>5} {:>7} {:>8}",
+                        rank, result.board, win.draw_index, win.number, win.score
+                    )?;
+                }
+                None => writeln!(
+                    f,
+                    "{:>5} {:>6} {:>5} {:>7} {:>8}",
+                    "-", result.board, "-", "-", "-"
+                )?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn play(game: &mut Game, numbers: &[u32]) -> Timeline {
+    let mut results = Vec::with_capacity(game.boards.len());
+
+    for (draw_index, number) in numbers.iter().enumerate() {
+        if results.len() == game.boards.len() {
+            break;
+        }
+
+        let winners = game.call(*number);
+        let rank = results.len() + 1;
+        let tied = winners.len() > 1;
+        for b in winners {
+            results.push(BoardResult {
+                board: b,
+                win: Some(Win {
+                    draw_index,
+                    number: *number,
+                    score: game.score(b, *number),
+                    rank,
+                    tied,
+                }),
+            });
+        }
+    }
+
+    for (b, board) in game.boards.iter().enumerate() {
+        if !board.has_won {
+            results.push(BoardResult {
+                board: b,
+                win: None,
+            });
+        }
+    }
+
+    Timeline { results }
+}
+
 fn main() -> io::Result<()> {
     let text = read_text("4/input.txt")?;
 
     let mut lines = text.lines();
-    let callout_numbers: Vec<&str> = lines.next().unwrap().split(",").collect();
-    let mut boards = parse_boards(lines);
+    let callout_numbers: Vec<u32> = lines.next().unwrap().split(',').map(parse_num).collect();
+
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let mut timeline_format = None;
+    if let Some(i) = args.iter().position(|arg| arg == "--timeline") {
+        timeline_format = Some(
+            args.get(i + 1)
+                .expect("--timeline needs json or table")
+                .clone(),
+        );
+        args.drain(i..=i + 1);
+    }
+
+    let mut game = Game::new(parse_boards(lines), Rules::from_args(&args));
+    let timeline = play(&mut game, &callout_numbers);
+
+    println!("p1 {}", timeline.first().map_or(0, |win| win.score));
+    println!("p2 {}", timeline.last().map_or(0, |win| win.score));
+
+    match timeline_format.as_deref() {
+        Some("json") => println!("{}", timeline.to_json()),
+        Some("table") => print!("{}", timeline),
+        Some(format) => panic!("Unknown timeline format {}", format),
+        None => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let mut first_board_score = 0;
-    let mut last_board_score = 0;
+    const EXAMPLE: &str = "7,4,9,5,11,17,23,2,0,14,21,24,10,16,13,6,15,25,12,22,18,20,8,19,3,26,1
+
+22 13 17 11  0
+ 8  2 23  4 24
+21  9 14 16  7
+ 6 10  3 18  5
+ 1 12 20 15 19
+
+ 3 15  0  2 22
+ 9 18 13 17  5
+19  8  7 25 23
+20 11 10 24  4
+14 21 16 12  6
+
+14 21 17 24  4
+10 16 15  9 19
+18  8 23 26 20
+22 11 13  6  5
+ 2  0 12  3  7";
+
+    fn example_game() -> (Vec<u32>, Game) {
+        let mut lines = EXAMPLE.lines();
+        let numbers = lines.next().unwrap().split(',').map(parse_num).collect();
+        (numbers, Game::new(parse_boards(lines), Rules::default()))
+    }
 
-    for number in callout_numbers {
-        let mut board_score = 0;
-        for board in &mut boards {
-            if board.numbers.contains(number) {
-                board.called_numbers.insert(number.to_owned());
+    #[test]
+    fn test_call_finds_winners_in_order() {
+        let (numbers, mut game) = example_game();
+        let wins = call_all(&mut game, &numbers);
+
+        assert_eq!(wins.first(), Some(&(2, 4512)));
+        assert_eq!(wins.last(), Some(&(1, 1924)));
+        assert_eq!(wins.len(), 3);
+    }
+
+    #[test]
+    fn test_repeated_number_is_not_double_counted() {
+        let mut game = Game::new(
+            vec![Board::new(vec![vec![1, 2], vec![3, 4]])],
+            Rules::default(),
+        );
+        assert!(game.call(1).is_empty());
+        assert!(game.call(1).is_empty());
+        assert_eq!(game.boards[0].unmarked_sum, 9);
+        assert_eq!(game.call(3), vec![0]);
+    }
+
+    fn call_all(game: &mut Game, numbers: &[u32]) -> Vec<(usize, u32)> {
+        let mut wins = Vec::new();
+        for number in numbers {
+            for b in game.call(*number) {
+                wins.push((b, game.score(b, *number)));
             }
         }
+        wins
+    }
+
+    #[test]
+    fn test_rectangular_board() {
+        let board = Board::new(vec![vec![1, 2, 3], vec![4, 5, 6]]);
+        let rules = Rules {
+            size: Some((2, 3)),
+            ..Rules::default()
+        };
+        let mut game = Game::new(vec![board], rules);
+        // a column only needs two numbers on a 2x3 board
+        assert_eq!(call_all(&mut game, &[2, 5]), vec![(0, 5 * 14)]);
+    }
 
-        boards = boards
+    #[test]
+    fn test_diagonal_win() {
+        let board = Board::new(vec![vec![1, 2, 3], vec![4, 5, 6], vec![7, 8, 9]]);
+        let rules = Rules {
+            diagonals: true,
+            ..Rules::default()
+        };
+        let mut game = Game::new(vec![board], rules);
+        assert_eq!(call_all(&mut game, &[3, 5, 7]), vec![(0, 7 * 30)]);
+    }
+
+    #[test]
+    fn test_multiple_lines_and_blackout() {
+        let grid = vec![vec![1, 2], vec![3, 4]];
+        let rules = Rules {
+            win: WinCondition::Lines(2),
+            ..Rules::default()
+        };
+        let mut game = Game::new(vec![Board::new(grid.clone())], rules);
+        // 1 and 2 complete a row, 3 completes the first column as a second line
+        assert_eq!(call_all(&mut game, &[1, 2, 3]), vec![(0, 3 * 4)]);
+
+        let rules = Rules {
+            win: WinCondition::Blackout,
+            ..Rules::default()
+        };
+        let mut game = Game::new(vec![Board::new(grid)], rules);
+        assert!(call_all(&mut game, &[1, 2, 3]).is_empty());
+        assert_eq!(call_all(&mut game, &[4]), vec![(0, 4 * 10)]);
+    }
+
+    #[test]
+    fn test_rules_from_args() {
+        let args: Vec<String> = ["--diagonals", "--lines", "2", "--size", "5x5"]
             .iter()
-            .filter(|board| {
-                if check_if_board_has_won(board) {
-                    let mut sum = 0;
-                    for row in &board.grid {
-                        for col in row {
-                            if !board.called_numbers.contains(col) {
-                                sum += parse_num(col);
-                            }
-                        }
-                    }
-                    board_score = sum * parse_num(number);
-                    if first_board_score == 0 {
-                        first_board_score = board_score
-                    }
-                    false
-                } else {
-                    true
-                }
-            })
-            .cloned()
+            .map(|arg| arg.to_string())
             .collect();
+        assert_eq!(
+            Rules::from_args(&args),
+            Rules {
+                size: Some((5, 5)),
+                diagonals: true,
+                win: WinCondition::Lines(2),
+            }
+        );
+    }
 
-        if boards.len() == 0 {
-            last_board_score = board_score;
-            break;
-        }
+    #[test]
+    fn test_timeline() {
+        let (numbers, mut game) = example_game();
+        let timeline = play(&mut game, &numbers);
+
+        assert_eq!(timeline.first().unwrap().score, 4512);
+        assert_eq!(timeline.last().unwrap().score, 1924);
+        let order: Vec<usize> = timeline.results.iter().map(|result| result.board).collect();
+        assert_eq!(order, vec![2, 0, 1]);
+        assert_eq!(
+            timeline.results[0].win,
+            Some(Win {
+                draw_index: 11,
+                number: 24,
+                score: 4512,
+                rank: 1,
+                tied: false,
+            })
+        );
     }
 
-    println!("p1 {}", first_board_score);
-    println!("p2 {}", last_board_score);
+    #[test]
+    fn test_timeline_ties_and_unfinished_boards() {
+        let boards = vec![
+            Board::new(vec![vec![1, 6], vec![3, 4]]),
+            Board::new(vec![vec![5, 6], vec![7, 8]]),
+            Board::new(vec![vec![1, 6], vec![9, 10]]),
+            Board::new(vec![vec![11, 12], vec![13, 14]]),
+        ];
+        let mut game = Game::new(boards, Rules::default());
+        let timeline = play(&mut game, &[5, 6, 1, 2]);
 
-    Ok(())
+        let ranks: Vec<(usize, Option<usize>, bool)> = timeline
+            .results
+            .iter()
+            .map(|result| {
+                (
+                    result.board,
+                    result.win.as_ref().map(|win| win.rank),
+                    result.win.as_ref().is_some_and(|win| win.tied),
+                )
+            })
+            .collect();
+        assert_eq!(
+            ranks,
+            vec![
+                (1, Some(1), false),
+                (0, Some(2), true),
+                (2, Some(2), true),
+                (3, None, false),
+            ]
+        );
+
+        assert_eq!(
+            timeline.to_json(),
+            "{\"boards\":[\
+             {\"board\":1,\"draw_index\":1,\"number\":6,\"score\":90,\"rank\":1,\"tied\":false},\
+             {\"board\":0,\"draw_index\":2,\"number\":1,\"score\":7,\"rank\":2,\"tied\":true},\
+             {\"board\":2,\"draw_index\":2,\"number\":1,\"score\":19,\"rank\":2,\"tied\":true},\
+             {\"board\":3,\"draw_index\":null,\"number\":null,\"score\":null,\"rank\":null,\"tied\":false}\
+             ]}"
+        );
+    }
 }
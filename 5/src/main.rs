@@ -1,22 +1,110 @@
 use std::collections::HashMap;
-use std::io::Result;
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Result, Write};
 
+use num_bigint::BigInt;
 use read_input::read_text;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Rasterisation {
+    // horizontal, vertical and 45 degree lines only, as in the puzzle
+    Restricted,
+    // integer Bresenham stepping, for segments of any slope
+    Bresenham,
+}
+
 struct Line {
-    start: (i32, i32),
-    end: (i32, i32),
+    start: (i64, i64),
+    end: (i64, i64),
 }
 
 impl Line {
-    fn delta(&self) -> (i32, i32) {
+    fn delta(&self) -> (i64, i64) {
         (self.end.0 - self.start.0, self.end.1 - self.start.1)
     }
+
+    fn points(&self, rasterisation: Rasterisation) -> Vec<(i64, i64)> {
+        match rasterisation {
+            Rasterisation::Restricted => self.restricted_points(),
+            Rasterisation::Bresenham => self.bresenham_points(),
+        }
+    }
+
+    fn restricted_points(&self) -> Vec<(i64, i64)> {
+        let delta = self.delta();
+        let delta_x = delta.0.abs();
+        let delta_y = delta.1.abs();
+        if delta_x != 0 && delta_y != 0 && delta_x != delta_y {
+            panic!(
+                "Line {:?} -> {:?} is not horizontal, vertical or 45 degrees",
+                self.start, self.end
+            );
+        }
+
+        let mut points = Vec::with_capacity(delta_x.max(delta_y) as usize + 1);
+        let sx = self.start.0;
+        let sy = self.start.1;
+        let mut x_incr = 0;
+        let mut y_incr = 0;
+        loop {
+            points.push((
+                get_insert_coord_value(delta.0, sx, x_incr),
+                get_insert_coord_value(delta.1, sy, y_incr),
+            ));
+
+            let mut did_increment = false;
+            if delta_x > 0 && x_incr < delta_x {
+                x_incr += 1;
+                did_increment = true;
+            }
+            if delta_y > 0 && y_incr < delta_y {
+                y_incr += 1;
+                did_increment = true;
+            }
+            if !did_increment {
+                break;
+            }
+        }
+
+        points
+    }
+
+    fn bresenham_points(&self) -> Vec<(i64, i64)> {
+        let (end_x, end_y) = self.end;
+        let dx = (end_x - self.start.0).abs();
+        let dy = -(end_y - self.start.1).abs();
+        let step_x = if self.start.0 < end_x { 1 } else { -1 };
+        let step_y = if self.start.1 < end_y { 1 } else { -1 };
+
+        let mut points = Vec::with_capacity(dx.max(-dy) as usize + 1);
+        let (mut x, mut y) = self.start;
+        // err tracks dx * (distance above the ideal line) - dy * (distance
+        // along it), scaled so everything stays in integers
+        let mut err = dx + dy;
+        loop {
+            points.push((x, y));
+            if x == end_x && y == end_y {
+                break;
+            }
+
+            let doubled = 2 * err;
+            if doubled >= dy {
+                err += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                err += dx;
+                y += step_y;
+            }
+        }
+
+        points
+    }
 }
 
-fn get_number(value: Option<&str>) -> i32 {
+fn get_number(value: Option<&str>) -> i64 {
     if let Some(value) = value {
-        match value.parse::<i32>() {
+        match value.parse::<i64>() {
             Ok(n) => n,
             Err(_err) => panic!("Could not parse number {:?}", value),
         }
@@ -25,9 +113,9 @@ fn get_number(value: Option<&str>) -> i32 {
     }
 }
 
-fn get_coords_from_point_str(point: Option<&str>) -> (i32, i32) {
+fn get_coords_from_point_str(point: Option<&str>) -> (i64, i64) {
     if let Some(point) = point {
-        let mut axis = point.split(",");
+        let mut axis = point.split(',');
         let x = get_number(axis.next());
         let y = get_number(axis.next());
 
@@ -37,7 +125,7 @@ fn get_coords_from_point_str(point: Option<&str>) -> (i32, i32) {
     }
 }
 
-fn get_insert_coord_value(delta: i32, start: i32, incr: i32) -> i32 {
+fn get_insert_coord_value(delta: i64, start: i64, incr: i64) -> i64 {
     if delta < 0 {
         start - incr
     } else {
@@ -45,7 +133,7 @@ fn get_insert_coord_value(delta: i32, start: i32, incr: i32) -> i32 {
     }
 }
 
-fn get_line_segments(text: &String, include_diagonal: bool) -> Vec<Line> {
+fn get_line_segments(text: &str, include_diagonal: bool) -> Vec<Line> {
     let mut segments = Vec::new();
 
     for line in text.lines() {
@@ -55,8 +143,8 @@ fn get_line_segments(text: &String, include_diagonal: bool) -> Vec<Line> {
 
         if include_diagonal || coord_one.0 == coord_two.0 || coord_one.1 == coord_two.1 {
             segments.push(Line {
-                start: coord_one.into(),
-                end: coord_two.into(),
+                start: coord_one,
+                end: coord_two,
             });
         }
     }
@@ -64,69 +152,685 @@ fn get_line_segments(text: &String, include_diagonal: bool) -> Vec<Line> {
     segments
 }
 
-fn get_grid_of_line_coords(segments: &Vec<Line>) -> HashMap<(i32, i32), i32> {
+fn get_grid_of_line_coords(
+    segments: &[Line],
+    rasterisation: Rasterisation,
+) -> HashMap<(i64, i64), i32> {
     let mut intersection_points = HashMap::new();
     for line in segments {
-        let delta = line.delta();
-        let sx = line.start.0;
-        let sy = line.start.1;
-        let mut x_incr = 0;
-        let mut y_incr = 0;
-        let delta_x = delta.0.abs();
-        let delta_y = delta.1.abs();
-        loop {
-            let insert_x = get_insert_coord_value(delta.0, sx, x_incr);
-            let insert_y = get_insert_coord_value(delta.1, sy, y_incr);
+        for point in line.points(rasterisation) {
+            *intersection_points.entry(point).or_insert(0) += 1;
+        }
+    }
 
-            if intersection_points.contains_key(&(insert_x, insert_y)) {
-                let count = intersection_points.get_mut(&(insert_x, insert_y)).unwrap();
-                *count += 1;
-            } else {
-                intersection_points.insert((insert_x, insert_y), 1);
+    intersection_points
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// A difference of two i64s takes up to 65 bits, so the product of two of them
+// can outgrow an i128
+fn cross(a: (i128, i128), b: (i128, i128)) -> BigInt {
+    BigInt::from(a.0) * b.1 - BigInt::from(a.1) * b.0
+}
+
+fn span(from: i64, to: i64) -> i128 {
+    to as i128 - from as i128
+}
+
+// A segment as its lattice points start + k * step for k in 0..=steps
+struct LatticeSegment {
+    start: (i64, i64),
+    step: (i128, i128),
+    steps: i128,
+}
+
+impl LatticeSegment {
+    fn new(line: &Line) -> Self {
+        let (dx, dy) = (
+            span(line.start.0, line.end.0),
+            span(line.start.1, line.end.1),
+        );
+        let steps = gcd(dx, dy);
+        if steps == 0 {
+            return LatticeSegment {
+                start: line.start,
+                step: (0, 0),
+                steps: 0,
+            };
+        }
+
+        // point every step the same way so collinear segments share a key
+        let (mut step, mut start) = ((dx / steps, dy / steps), line.start);
+        if step.0 < 0 || (step.0 == 0 && step.1 < 0) {
+            step = (-step.0, -step.1);
+            start = line.end;
+        }
+
+        LatticeSegment { start, step, steps }
+    }
+
+    fn is_point(&self) -> bool {
+        self.steps == 0
+    }
+
+    fn point_at(&self, k: i128) -> (i64, i64) {
+        (
+            (self.start.0 as i128 + k * self.step.0) as i64,
+            (self.start.1 as i128 + k * self.step.1) as i64,
+        )
+    }
+
+    // Identifies the infinite line the segment lies on
+    fn line_key(&self) -> (i128, i128, BigInt) {
+        let offset = cross(self.step, (self.start.0 as i128, self.start.1 as i128));
+        (self.step.0, self.step.1, offset)
+    }
+
+    // Position along the line, x unless the line is vertical. Lattice points
+    // on the same line are all congruent modulo the matching step component.
+    fn along(&self, point: (i64, i64)) -> i128 {
+        if self.step.0 != 0 {
+            point.0 as i128
+        } else {
+            point.1 as i128
+        }
+    }
+
+    fn along_step(&self) -> i128 {
+        if self.step.0 != 0 {
+            self.step.0
+        } else {
+            self.step.1
+        }
+    }
+
+    fn x_range(&self) -> (i64, i64) {
+        let end = self.point_at(self.steps);
+        (self.start.0.min(end.0), self.start.0.max(end.0))
+    }
+
+    // The index k of the point on this segment, if the point lies on it
+    fn index_of(&self, point: (i64, i64)) -> Option<i128> {
+        let offset = (span(self.start.0, point.0), span(self.start.1, point.1));
+        if self.is_point() {
+            return if offset == (0, 0) { Some(0) } else { None };
+        }
+        if cross(self.step, offset) != BigInt::ZERO {
+            return None;
+        }
+
+        let k = if self.step.0 != 0 {
+            offset.0 / self.step.0
+        } else {
+            offset.1 / self.step.1
+        };
+        if (0..=self.steps).contains(&k) {
+            Some(k)
+        } else {
+            None
+        }
+    }
+
+    // The single lattice point two non-parallel segments share, if any
+    fn crossing(&self, other: &LatticeSegment) -> Option<(i64, i64)> {
+        if self.is_point() {
+            return other.index_of(self.start).map(|_| self.start);
+        }
+        if other.is_point() {
+            return self.index_of(other.start).map(|_| other.start);
+        }
+
+        let denom = cross(self.step, other.step);
+        if denom == BigInt::ZERO {
+            return None;
+        }
+
+        let offset = (
+            span(self.start.0, other.start.0),
+            span(self.start.1, other.start.1),
+        );
+        let k = cross(offset, other.step);
+        let j = cross(offset, self.step);
+        if &k % &denom != BigInt::ZERO || &j % &denom != BigInt::ZERO {
+            return None;
+        }
+
+        // an index too large for an i128 is past the end of either segment
+        let index = |n: BigInt, steps: i128| {
+            i128::try_from(n / &denom)
+                .ok()
+                .filter(|n| (0..=steps).contains(n))
+        };
+        match (index(k, self.steps), index(j, other.steps)) {
+            (Some(k), Some(_)) => Some(self.point_at(k)),
+            _ => None,
+        }
+    }
+}
+
+// Counts the lattice points covered by at least two segments without visiting
+// every covered point. Collinear segments are swept along their shared line,
+// every other pair can only meet at a single point. A point where segments
+// cross can also sit inside collinear overlaps, so it is counted once here
+// and any counts from the sweeps are taken back out.
+//
+// Unlike the rasteriser this counts the exact lattice points on each segment,
+// which matches Rasterisation::Restricted for the puzzle's lines.
+fn count_overlaps_analytic(segments: &[Line]) -> u128 {
+    let lattice: Vec<LatticeSegment> = segments.iter().map(LatticeSegment::new).collect();
+
+    let mut lines: HashMap<(i128, i128, BigInt), Vec<usize>> = HashMap::new();
+    for (i, segment) in lattice.iter().enumerate() {
+        if !segment.is_point() {
+            lines.entry(segment.line_key()).or_default().push(i);
+        }
+    }
+
+    let mut count = 0u128;
+    for members in lines.values() {
+        if members.len() < 2 {
+            continue;
+        }
+
+        let step = lattice[members[0]].along_step();
+        let mut events: Vec<(i128, i32)> = Vec::with_capacity(members.len() * 2);
+        for i in members {
+            let segment = &lattice[*i];
+            let start = segment.along(segment.start);
+            events.push((start, 1));
+            events.push((start + segment.steps * step + step, -1));
+        }
+        events.sort_unstable();
+
+        let mut coverage = 0;
+        for pair in events.windows(2) {
+            coverage += pair[0].1;
+            if coverage >= 2 {
+                count += ((pair[1].0 - pair[0].0) / step) as u128;
             }
+        }
+    }
 
-            let mut did_increment = false;
-            if delta_x > 0 && x_incr < delta_x {
-                x_incr += 1;
-                did_increment = true;
+    // sweep along x so only segments whose x ranges overlap are paired up
+    let mut order: Vec<usize> = (0..lattice.len()).collect();
+    order.sort_by_key(|i| lattice[*i].x_range().0);
+
+    let mut crossings: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (n, i) in order.iter().enumerate() {
+        let max_x = lattice[*i].x_range().1;
+        for j in order.iter().skip(n + 1) {
+            if lattice[*j].x_range().0 > max_x {
+                break;
             }
-            if delta_y > 0 && y_incr < delta_y {
-                y_incr += 1;
-                did_increment = true;
+            if let Some(point) = lattice[*i].crossing(&lattice[*j]) {
+                let through = crossings.entry(point).or_default();
+                through.push(*i);
+                through.push(*j);
             }
-            if !did_increment {
-                break;
+        }
+    }
+
+    // Every segment through a crossing point is recorded: it can't be parallel
+    // to both of the segments that cross there.
+    let mut overcounted = 0u128;
+    for through in crossings.values_mut() {
+        through.sort_unstable();
+        through.dedup();
+
+        let mut per_line: HashMap<(i128, i128, BigInt), usize> = HashMap::new();
+        for i in through.iter() {
+            if !lattice[*i].is_point() {
+                *per_line.entry(lattice[*i].line_key()).or_insert(0) += 1;
             }
         }
+        let swept = per_line.values().filter(|n| **n >= 2).count() as u128;
+        if swept == 0 {
+            count += 1;
+        } else {
+            overcounted += swept - 1;
+        }
     }
 
-    intersection_points
+    count - overcounted
 }
 
-fn main() -> Result<()> {
-    let text = read_text("5/input.txt")?;
+fn count_overlaps(segments: &[Line], rasterisation: Rasterisation) -> usize {
+    get_grid_of_line_coords(segments, rasterisation)
+        .iter()
+        .filter(|&(_coord, count)| *count > 1)
+        .count()
+}
 
-    let segments = get_line_segments(&text, false);
-    let intersection_points = get_grid_of_line_coords(&segments);
+// The overlap counts of every covered cell, for exporting and querying
+struct VentMap {
+    counts: HashMap<(i64, i64), i32>,
+    min: (i64, i64),
+    max: (i64, i64),
+}
 
-    println!(
-        "{}",
-        intersection_points
-            .iter()
-            .filter(|&(_coord, count)| { *count > 1 })
-            .count()
-    );
+// Images larger than this on either side are refused rather than allocated
+const MAX_IMAGE_SIDE: i64 = 8192;
 
-    let segments = get_line_segments(&text, true);
-    let intersection_points = get_grid_of_line_coords(&segments);
+impl VentMap {
+    fn new(counts: HashMap<(i64, i64), i32>) -> Self {
+        let mut min = (i64::MAX, i64::MAX);
+        let mut max = (i64::MIN, i64::MIN);
+        for (x, y) in counts.keys() {
+            min = (min.0.min(*x), min.1.min(*y));
+            max = (max.0.max(*x), max.1.max(*y));
+        }
+        if counts.is_empty() {
+            min = (0, 0);
+            max = (0, 0);
+        }
+
+        VentMap { counts, min, max }
+    }
 
-    println!(
-        "{}",
-        intersection_points
+    fn coverage(&self, point: (i64, i64)) -> i32 {
+        *self.counts.get(&point).unwrap_or(&0)
+    }
+
+    // The n most covered cells, ties broken by y then x so output is stable
+    fn hottest(&self, n: usize) -> Vec<((i64, i64), i32)> {
+        let mut cells: Vec<((i64, i64), i32)> = self
+            .counts
             .iter()
-            .filter(|&(_coord, count)| { *count > 1 })
+            .map(|(point, count)| (*point, *count))
+            .collect();
+        cells.sort_by(|a, b| {
+            b.1.cmp(&a.1)
+                .then(a.0 .1.cmp(&b.0 .1))
+                .then(a.0 .0.cmp(&b.0 .0))
+        });
+        cells.truncate(n);
+        cells
+    }
+
+    // Cells with a count of at least `threshold` inside the inclusive rectangle
+    fn count_in_region(
+        &self,
+        corner: (i64, i64),
+        other_corner: (i64, i64),
+        threshold: i32,
+    ) -> usize {
+        let (x0, x1) = (corner.0.min(other_corner.0), corner.0.max(other_corner.0));
+        let (y0, y1) = (corner.1.min(other_corner.1), corner.1.max(other_corner.1));
+        self.counts
+            .iter()
+            .filter(|((x, y), count)| {
+                **count >= threshold && (x0..=x1).contains(x) && (y0..=y1).contains(y)
+            })
             .count()
-    );
+    }
+
+    fn image_size(&self) -> Result<(usize, usize)> {
+        // a span wider than i64 is far past the limit too
+        let side = |min: i64, max: i64| {
+            max.checked_sub(min)
+                .and_then(|span| span.checked_add(1))
+                .filter(|side| *side <= MAX_IMAGE_SIDE)
+        };
+        match (side(self.min.0, self.max.0), side(self.min.1, self.max.1)) {
+            (Some(width), Some(height)) => Ok((width as usize, height as usize)),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "({}, {}) to ({}, {}) is too large for a heatmap",
+                    self.min.0, self.min.1, self.max.0, self.max.1
+                ),
+            )),
+        }
+    }
+
+    // Each cell's count scaled to 0..=255 against the hottest cell, row by row
+    fn intensities(&self) -> Result<(usize, usize, Vec<u8>)> {
+        let (width, height) = self.image_size()?;
+        let hottest = *self.counts.values().max().unwrap_or(&1) as i64;
+        let mut pixels = vec![0u8; width * height];
+        for ((x, y), count) in &self.counts {
+            let i = (y - self.min.1) as usize * width + (x - self.min.0) as usize;
+            pixels[i] = (*count as i64 * 255 / hottest) as u8;
+        }
+
+        Ok((width, height, pixels))
+    }
+
+    fn write_pgm(&self, out: &mut impl Write) -> Result<()> {
+        let (width, height, pixels) = self.intensities()?;
+        write!(out, "P5\n{} {}\n255\n", width, height)?;
+        out.write_all(&pixels)
+    }
+
+    // Black for uncovered cells, then blue through to red as counts rise
+    fn write_ppm(&self, out: &mut impl Write) -> Result<()> {
+        let (width, height, pixels) = self.intensities()?;
+        write!(out, "P6\n{} {}\n255\n", width, height)?;
+        let mut rgb = Vec::with_capacity(pixels.len() * 3);
+        for value in pixels {
+            if value == 0 {
+                rgb.extend_from_slice(&[0, 0, 0]);
+            } else {
+                let green = 255 - (2 * value as i32 - 255).unsigned_abs() as u8;
+                rgb.extend_from_slice(&[value, green, 255 - value]);
+            }
+        }
+        out.write_all(&rgb)
+    }
+}
+
+fn parse_point(value: Option<&String>) -> (i64, i64) {
+    get_coords_from_point_str(value.map(|value| value.as_str()))
+}
+
+fn main() -> Result<()> {
+    let text = read_text("5/input.txt")?;
+
+    let mut rasterisation = Rasterisation::Restricted;
+    let mut analytic = false;
+    let mut heatmap = None;
+    let mut at = Vec::new();
+    let mut hottest = None;
+    let mut regions = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--any-angle" => rasterisation = Rasterisation::Bresenham,
+            "--analytic" => analytic = true,
+            // --heatmap out.pgm or out.ppm
+            "--heatmap" => heatmap = Some(args.next().expect("--heatmap needs a path")),
+            // --at X,Y
+            "--at" => at.push(parse_point(args.next().as_ref())),
+            // --hottest N
+            "--hottest" => hottest = Some(get_number(args.next().as_deref()) as usize),
+            // --region X0,Y0 X1,Y1 K
+            "--region" => regions.push((
+                parse_point(args.next().as_ref()),
+                parse_point(args.next().as_ref()),
+                get_number(args.next().as_deref()) as i32,
+            )),
+            _ => panic!("Unknown argument {}", arg),
+        }
+    }
+    if analytic && rasterisation == Rasterisation::Bresenham {
+        panic!("--analytic counts exact lattice points, it can't be combined with --any-angle");
+    }
+    // the queries and heatmap work on every covered cell, which is what
+    // --analytic is there to avoid building
+    let queried = !at.is_empty() || hottest.is_some() || !regions.is_empty() || heatmap.is_some();
+    if analytic && queried {
+        panic!("--at, --hottest, --region and --heatmap rasterise every line, they can't be combined with --analytic");
+    }
+
+    for include_diagonal in [false, true] {
+        let segments = get_line_segments(&text, include_diagonal);
+        if analytic {
+            println!("{}", count_overlaps_analytic(&segments));
+        } else {
+            println!("{}", count_overlaps(&segments, rasterisation));
+        }
+    }
+
+    if !queried {
+        return Ok(());
+    }
+
+    let vent_map = VentMap::new(get_grid_of_line_coords(
+        &get_line_segments(&text, true),
+        rasterisation,
+    ));
+    for point in at {
+        println!("{:?}: {}", point, vent_map.coverage(point));
+    }
+    if let Some(n) = hottest {
+        for (point, count) in vent_map.hottest(n) {
+            println!("{:?}: {}", point, count);
+        }
+    }
+    for (corner, other_corner, threshold) in regions {
+        println!(
+            "{:?} to {:?} with at least {}: {}",
+            corner,
+            other_corner,
+            threshold,
+            vent_map.count_in_region(corner, other_corner, threshold)
+        );
+    }
+    if let Some(path) = heatmap {
+        let mut out = BufWriter::new(File::create(&path)?);
+        if path.ends_with(".ppm") {
+            vent_map.write_ppm(&mut out)?;
+        } else {
+            vent_map.write_pgm(&mut out)?;
+        }
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(start: (i64, i64), end: (i64, i64)) -> Line {
+        Line { start, end }
+    }
+
+    const EXAMPLE: &str = "0,9 -> 5,9
+8,0 -> 0,8
+9,4 -> 3,4
+2,2 -> 2,1
+7,0 -> 7,4
+6,4 -> 2,0
+0,9 -> 2,9
+3,4 -> 1,4
+0,0 -> 8,8
+5,5 -> 8,2";
+
+    // small deterministic generator so the cross checks don't need a crate
+    fn next_random(seed: &mut u64) -> i64 {
+        *seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (*seed >> 33) as i64
+    }
+
+    // Counts exact lattice points by walking each segment
+    fn count_overlaps_brute_force(segments: &[Line]) -> u128 {
+        let mut covered: HashMap<(i64, i64), i32> = HashMap::new();
+        for segment in segments {
+            let lattice = LatticeSegment::new(segment);
+            for k in 0..=lattice.steps {
+                *covered.entry(lattice.point_at(k)).or_insert(0) += 1;
+            }
+        }
+        covered.values().filter(|count| **count > 1).count() as u128
+    }
+
+    #[test]
+    fn test_modes_agree_on_puzzle_lines() {
+        for segment in [
+            line((0, 9), (5, 9)),
+            line((7, 0), (7, 4)),
+            line((8, 0), (0, 8)),
+            line((6, 4), (2, 0)),
+            line((3, 3), (3, 3)),
+        ] {
+            assert_eq!(
+                segment.points(Rasterisation::Restricted),
+                segment.points(Rasterisation::Bresenham)
+            );
+        }
+    }
+
+    #[test]
+    fn test_bresenham_any_slope() {
+        assert_eq!(
+            line((0, 0), (4, 2)).points(Rasterisation::Bresenham),
+            vec![(0, 0), (1, 1), (2, 1), (3, 2), (4, 2)]
+        );
+        assert_eq!(
+            line((0, 0), (-1, -3)).points(Rasterisation::Bresenham),
+            vec![(0, 0), (0, -1), (-1, -2), (-1, -3)]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "not horizontal, vertical or 45 degrees")]
+    fn test_restricted_rejects_other_slopes() {
+        line((0, 0), (4, 2)).points(Rasterisation::Restricted);
+    }
+
+    #[test]
+    fn test_analytic_matches_example() {
+        assert_eq!(
+            count_overlaps_analytic(&get_line_segments(EXAMPLE, false)),
+            5
+        );
+        assert_eq!(
+            count_overlaps_analytic(&get_line_segments(EXAMPLE, true)),
+            12
+        );
+    }
+
+    #[test]
+    fn test_analytic_matches_rasterising() {
+        let mut seed = 5;
+        for _ in 0..50 {
+            let mut segments = Vec::new();
+            for _ in 0..40 {
+                let start = (next_random(&mut seed) % 30, next_random(&mut seed) % 30);
+                let length = next_random(&mut seed) % 15;
+                let (dx, dy) = match next_random(&mut seed) % 4 {
+                    0 => (1, 0),
+                    1 => (0, 1),
+                    2 => (1, 1),
+                    _ => (1, -1),
+                };
+                let flip = if next_random(&mut seed) % 2 == 0 {
+                    1
+                } else {
+                    -1
+                };
+                let end = (start.0 + dx * length * flip, start.1 + dy * length * flip);
+                segments.push(line(start, end));
+            }
+
+            assert_eq!(
+                count_overlaps_analytic(&segments),
+                count_overlaps(&segments, Rasterisation::Restricted) as u128
+            );
+        }
+    }
+
+    #[test]
+    fn test_analytic_any_slope() {
+        let mut seed = 31;
+        for _ in 0..50 {
+            let segments: Vec<Line> = (0..30)
+                .map(|_| {
+                    let mut point = || (next_random(&mut seed) % 12, next_random(&mut seed) % 12);
+                    line(point(), point())
+                })
+                .collect();
+
+            assert_eq!(
+                count_overlaps_analytic(&segments),
+                count_overlaps_brute_force(&segments)
+            );
+        }
+    }
+
+    #[test]
+    fn test_analytic_huge_coordinates() {
+        let far = i64::MAX - 10;
+        let segments = vec![
+            line((0, 0), (far, far)),
+            line((far, 0), (0, far)),
+            line((2, 2), (8, 8)),
+            line((far - 4, far - 4), (far, far)),
+        ];
+        // the two long diagonals only cross off the lattice, the short ones
+        // overlap the first diagonal at 7 and 5 points
+        assert_eq!(count_overlaps_analytic(&segments), 12);
+    }
+
+    #[test]
+    fn test_analytic_extreme_coordinates() {
+        let (min, max) = (i64::MIN, i64::MAX);
+        let segments = vec![
+            line((min, 0), (max, 0)),
+            line((0, min), (0, max)),
+            line((min, min), (max, max)),
+            line((max, min), (min, max)),
+            line((-5, 0), (5, 0)),
+            line((min, min), (min + 2, min + 2)),
+        ];
+        // the axes and the first diagonal cross at the origin, inside the 11
+        // points the short horizontal shares with the x axis, and the short
+        // diagonal shares 3 points with the first one. The second diagonal
+        // runs along x + y = -1, so it crosses the axes at (-1, 0), also in
+        // the short horizontal, and at (0, -1).
+        assert_eq!(count_overlaps_analytic(&segments), 11 + 3 + 1);
+        assert_eq!(
+            LatticeSegment::new(&segments[0]).crossing(&LatticeSegment::new(&segments[1])),
+            Some((0, 0))
+        );
+    }
+
+    #[test]
+    fn test_vent_map_queries() {
+        let segments = get_line_segments(EXAMPLE, true);
+        let vent_map = VentMap::new(get_grid_of_line_coords(
+            &segments,
+            Rasterisation::Restricted,
+        ));
+
+        assert_eq!(vent_map.coverage((2, 2)), 2);
+        assert_eq!(vent_map.coverage((4, 4)), 3);
+        assert_eq!(vent_map.coverage((9, 0)), 0);
+        assert_eq!(
+            vent_map.hottest(3),
+            vec![((4, 4), 3), ((6, 4), 3), ((7, 1), 2)]
+        );
+        assert_eq!(vent_map.count_in_region((0, 0), (9, 9), 2), 12);
+        assert_eq!(vent_map.count_in_region((4, 4), (0, 0), 2), 3);
+        assert_eq!(vent_map.count_in_region((0, 0), (9, 9), 3), 2);
+    }
+
+    #[test]
+    fn test_heatmap_export() {
+        let segments = vec![line((0, 0), (2, 0)), line((1, 0), (1, 1))];
+        let vent_map = VentMap::new(get_grid_of_line_coords(
+            &segments,
+            Rasterisation::Restricted,
+        ));
+
+        let mut pgm = Vec::new();
+        vent_map.write_pgm(&mut pgm).unwrap();
+        assert_eq!(pgm, b"P5\n3 2\n255\n\x7f\xff\x7f\x00\x7f\x00".to_vec());
+
+        let mut ppm = Vec::new();
+        vent_map.write_ppm(&mut ppm).unwrap();
+        assert_eq!(&ppm[..11], b"P6\n3 2\n255\n");
+        assert_eq!(ppm.len(), 11 + 3 * 6);
+        assert_eq!(&ppm[14..17], &[255, 0, 0]);
+
+        let vent_map = VentMap::new(get_grid_of_line_coords(
+            &[line((0, 0), (100_000, 0))],
+            Rasterisation::Restricted,
+        ));
+        assert!(vent_map.write_pgm(&mut Vec::new()).is_err());
+
+        // corners further apart than i64 can count
+        let far_apart = VentMap::new(HashMap::from([((i64::MIN, 0), 1), ((i64::MAX, 0), 1)]));
+        assert!(far_apart.write_ppm(&mut Vec::new()).is_err());
+    }
+}
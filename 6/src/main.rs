@@ -1,40 +1,381 @@
 use std::io::Result;
 
+use num_bigint::BigUint;
 use read_input::read_text;
 
-fn generate_pop(mut lantern_fish: [usize; 9], times: usize) {
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Model {
+    // the timer a fish goes back to after spawning
+    reset_timer: usize,
+    // the timer a newborn fish starts on
+    newborn_timer: usize,
+    // how many newborns each spawning fish produces
+    offspring: usize,
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Model {
+            reset_timer: 6,
+            newborn_timer: 8,
+            offspring: 1,
+        }
+    }
+}
+
+impl Model {
+    fn slots(&self) -> usize {
+        self.reset_timer.max(self.newborn_timer) + 1
+    }
+
+    fn parse_fish(&self, text: &str) -> Vec<usize> {
+        text.trim()
+            .split(',')
+            .map(|number| number.parse::<usize>().unwrap())
+            .fold(vec![0; self.slots()], |mut lantern_fish, num| {
+                if num >= lantern_fish.len() {
+                    panic!(
+                        "Timer {} is past the longest timer {}",
+                        num,
+                        self.slots() - 1
+                    );
+                }
+                lantern_fish[num] += 1;
+                lantern_fish
+            })
+    }
+
+    fn step<A: Arithmetic>(&self, arithmetic: &A, lantern_fish: &[A::Value]) -> Vec<A::Value> {
+        lantern_fish.iter().enumerate().fold(
+            vec![arithmetic.count(0); lantern_fish.len()],
+            |mut new_lantern_fish, (i, n)| {
+                let mut add = |timer: usize, n: &A::Value| {
+                    new_lantern_fish[timer] = arithmetic.add(&new_lantern_fish[timer], n)
+                };
+                if i == 0 {
+                    add(self.reset_timer, n);
+                    add(
+                        self.newborn_timer,
+                        &arithmetic.mul(n, &arithmetic.count(self.offspring)),
+                    );
+                } else {
+                    add(i - 1, n);
+                }
+                new_lantern_fish
+            },
+        )
+    }
+}
+
+// Total population at the start and after each day, so the result has
+// times + 1 entries
+fn generate_pop<A: Arithmetic>(
+    arithmetic: &A,
+    model: &Model,
+    lantern_fish: &[usize],
+    times: usize,
+) -> Vec<A::Value> {
+    let total = |lantern_fish: &[A::Value]| {
+        lantern_fish
+            .iter()
+            .fold(arithmetic.count(0), |sum, n| arithmetic.add(&sum, n))
+    };
+
+    let mut lantern_fish: Vec<A::Value> =
+        lantern_fish.iter().map(|n| arithmetic.count(*n)).collect();
+    let mut series = Vec::with_capacity(times + 1);
+    series.push(total(&lantern_fish));
     for _ in 0..times {
-        lantern_fish =
-            lantern_fish
-                .iter()
-                .enumerate()
-                .fold([0; 9], |mut new_lantern_fish, (i, n)| {
-                    if i == 0 {
-                        new_lantern_fish[6] += n;
-                        new_lantern_fish[8] += n;
-                    } else {
-                        new_lantern_fish[i - 1] += n;
-                    }
-                    new_lantern_fish
-                });
+        lantern_fish = model.step(arithmetic, &lantern_fish);
+        series.push(total(&lantern_fish));
+    }
+
+    series
+}
+
+// The number type populations are computed in, both day by day and for the
+// matrix solver
+trait Arithmetic {
+    type Value: Clone;
+
+    fn count(&self, n: usize) -> Self::Value;
+    fn add(&self, a: &Self::Value, b: &Self::Value) -> Self::Value;
+    fn mul(&self, a: &Self::Value, b: &Self::Value) -> Self::Value;
+}
+
+// Populations modulo a prime, so any number of days stays in range
+struct Modular(u64);
+
+impl Arithmetic for Modular {
+    type Value = u64;
+
+    fn count(&self, n: usize) -> u64 {
+        (n as u64) % self.0
+    }
+
+    fn add(&self, a: &u64, b: &u64) -> u64 {
+        ((*a as u128 + *b as u128) % self.0 as u128) as u64
+    }
+
+    fn mul(&self, a: &u64, b: &u64) -> u64 {
+        ((*a as u128 * *b as u128) % self.0 as u128) as u64
+    }
+}
+
+// Exact populations. These grow by roughly 9% a day, so this is only practical
+// for day counts whose answers fit in memory.
+struct Exact;
+
+// Past this many days --days needs --modulo. The default model's answer is
+// already thousands of digits long here.
+const MAX_EXACT_DAYS: u64 = 100_000;
+
+impl Arithmetic for Exact {
+    type Value = BigUint;
+
+    fn count(&self, n: usize) -> BigUint {
+        BigUint::from(n)
+    }
+
+    fn add(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        a + b
+    }
+
+    fn mul(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        a * b
     }
+}
+
+type Matrix<T> = Vec<Vec<T>>;
 
-    println!("{}", lantern_fish.iter().sum::<usize>());
+fn mat_mul<A: Arithmetic>(
+    arithmetic: &A,
+    a: &Matrix<A::Value>,
+    b: &Matrix<A::Value>,
+) -> Matrix<A::Value> {
+    let size = a.len();
+    (0..size)
+        .map(|row| {
+            (0..size)
+                .map(|col| {
+                    (0..size).fold(arithmetic.count(0), |sum, k| {
+                        arithmetic.add(&sum, &arithmetic.mul(&a[row][k], &b[k][col]))
+                    })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Raises the matrix to the given power by repeated squaring
+fn mat_pow<A: Arithmetic>(
+    arithmetic: &A,
+    mut matrix: Matrix<A::Value>,
+    mut power: u64,
+) -> Matrix<A::Value> {
+    let size = matrix.len();
+    let mut result: Matrix<A::Value> = (0..size)
+        .map(|row| {
+            (0..size)
+                .map(|col| arithmetic.count((row == col) as usize))
+                .collect()
+        })
+        .collect();
+
+    while power > 0 {
+        if power & 1 == 1 {
+            result = mat_mul(arithmetic, &result, &matrix);
+        }
+        power >>= 1;
+        if power > 0 {
+            matrix = mat_mul(arithmetic, &matrix, &matrix);
+        }
+    }
+
+    result
+}
+
+impl Model {
+    // transition[to][from] is how many fish at timer `to` one fish at timer
+    // `from` becomes after a day
+    fn transition<A: Arithmetic>(&self, arithmetic: &A) -> Matrix<A::Value> {
+        let size = self.slots();
+        let mut counts = vec![vec![0; size]; size];
+        for from in 1..size {
+            counts[from - 1][from] += 1;
+        }
+        counts[self.reset_timer][0] += 1;
+        counts[self.newborn_timer][0] += self.offspring;
+
+        counts
+            .iter()
+            .map(|row| row.iter().map(|n| arithmetic.count(*n)).collect())
+            .collect()
+    }
+}
+
+// Total population after `days` in O(slots^3 log days) arithmetic operations
+fn population_after<A: Arithmetic>(
+    arithmetic: &A,
+    model: &Model,
+    lantern_fish: &[usize],
+    days: u64,
+) -> A::Value {
+    let matrix = mat_pow(arithmetic, model.transition(arithmetic), days);
+    // sums matrix[to][from] * lantern_fish[from] over every cell
+    matrix
+        .iter()
+        .flatten()
+        .zip(lantern_fish.iter().cycle())
+        .fold(arithmetic.count(0), |sum, (cell, count)| {
+            arithmetic.add(&sum, &arithmetic.mul(cell, &arithmetic.count(*count)))
+        })
 }
 
 fn main() -> Result<()> {
     let text = read_text("6/input.txt")?;
 
-    let lantern_fish = text
-        .split(",")
-        .map(|number| number.parse::<usize>().unwrap())
-        .fold([0; 9], |mut lantern_fish, num| {
-            lantern_fish[num % 9] += 1;
-            lantern_fish
-        });
+    let mut model = Model::default();
+    let mut days = None;
+    let mut modulus = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .map(|value| value.parse::<u64>().unwrap())
+            .unwrap_or_else(|| panic!("{} needs a number", arg));
+        match arg.as_str() {
+            "--reset" => model.reset_timer = value as usize,
+            "--newborn" => model.newborn_timer = value as usize,
+            "--offspring" => model.offspring = value as usize,
+            "--days" => days = Some(value),
+            "--modulo" => modulus = Some(value),
+            _ => panic!("Unknown argument {}", arg),
+        }
+    }
 
-    generate_pop(lantern_fish.clone(), 80);
-    generate_pop(lantern_fish, 256);
+    let lantern_fish = model.parse_fish(&text);
+
+    if let Some(days) = days {
+        match modulus {
+            Some(0) => panic!("--modulo needs a positive prime"),
+            Some(modulus) => println!(
+                "{}",
+                population_after(&Modular(modulus), &model, &lantern_fish, days)
+            ),
+            None if days > MAX_EXACT_DAYS => panic!(
+                "--days above {} needs --modulo, the exact population would be too large",
+                MAX_EXACT_DAYS
+            ),
+            None => println!("{}", population_after(&Exact, &model, &lantern_fish, days)),
+        }
+        return Ok(());
+    }
+
+    // exact, since a model with more offspring outgrows any fixed width
+    let series = generate_pop(&Exact, &model, &lantern_fish, 256);
+    println!("{}", series[80]);
+    println!("{}", series[256]);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_pop() {
+        let model = Model::default();
+        let series = generate_pop(&Exact, &model, &model.parse_fish("3,4,3,1,2"), 256);
+        assert_eq!(series[0], BigUint::from(5u32));
+        assert_eq!(series[18], BigUint::from(26u32));
+        assert_eq!(series[80], BigUint::from(5934u32));
+        assert_eq!(series[256], BigUint::from(26984457539u64));
+    }
+
+    #[test]
+    fn test_custom_timers() {
+        // every fish spawns two newborns each day and newborns spawn the next
+        let model = Model {
+            reset_timer: 0,
+            newborn_timer: 0,
+            offspring: 2,
+        };
+        let series = generate_pop(&Modular(1_000_000_007), &model, &model.parse_fish("0"), 4);
+        assert_eq!(series, vec![1, 3, 9, 27, 81]);
+    }
+
+    #[test]
+    fn test_many_offspring() {
+        // far past u64 by day 256, so the series has to be exact
+        let model = Model {
+            offspring: 50,
+            ..Model::default()
+        };
+        let lantern_fish = model.parse_fish("3,4,3,1,2");
+        let series = generate_pop(&Exact, &model, &lantern_fish, 256);
+        assert!(series[256].bits() > 64);
+        assert_eq!(
+            series[256],
+            population_after(&Exact, &model, &lantern_fish, 256)
+        );
+    }
+
+    #[test]
+    fn test_population_after_matches_simulation() {
+        let model = Model::default();
+        let lantern_fish = model.parse_fish("3,4,3,1,2");
+        let series = generate_pop(&Exact, &model, &lantern_fish, 256);
+        let modular = generate_pop(&Modular(1_000_000_007), &model, &lantern_fish, 256);
+        for days in [0, 1, 18, 80, 256] {
+            assert_eq!(
+                population_after(&Exact, &model, &lantern_fish, days as u64),
+                series[days]
+            );
+            assert_eq!(
+                population_after(&Modular(1_000_000_007), &model, &lantern_fish, days as u64),
+                modular[days]
+            );
+            assert_eq!(
+                &series[days] % 1_000_000_007u32,
+                BigUint::from(modular[days])
+            );
+        }
+    }
+
+    #[test]
+    fn test_population_after_huge_day_counts() {
+        let model = Model {
+            reset_timer: 0,
+            newborn_timer: 0,
+            offspring: 2,
+        };
+        let lantern_fish = model.parse_fish("0");
+        // one fish becomes 3^days fish, and 3^(p - 1) = 1 mod p
+        let prime = 1_000_000_007;
+        assert_eq!(
+            population_after(&Modular(prime), &model, &lantern_fish, prime - 1),
+            1
+        );
+        assert_eq!(
+            population_after(&Exact, &model, &lantern_fish, 100),
+            BigUint::from(3u32).pow(100)
+        );
+
+        let model = Model::default();
+        let lantern_fish = model.parse_fish("3,4,3,1,2");
+        let day = 1_000_000_000_000_000_000;
+        let counts: Vec<BigUint> = lantern_fish.iter().map(|n| BigUint::from(*n)).collect();
+        let stepped: Vec<usize> = model
+            .step(&Exact, &counts)
+            .iter()
+            .map(|n| usize::try_from(n).unwrap())
+            .collect();
+        // stepping a day by hand first has to land on the same population
+        assert_eq!(
+            population_after(&Modular(prime), &model, &lantern_fish, day),
+            population_after(&Modular(prime), &model, &stepped, day - 1)
+        );
+    }
+}
@@ -1,48 +1,392 @@
-use std::cmp;
 use std::io::Result;
 
 use read_input::read_text;
 
-fn calc_fuel(positions: &Vec<i32>, target: i32) -> i32 {
-    positions.iter().fold(0, |sum, n| sum + (*n - target).abs())
+#[derive(Clone, Debug, PartialEq)]
+enum FuelCost {
+    // each step costs 1
+    Linear,
+    // each step costs one more than the last
+    Triangular,
+    // moving d costs d * d
+    Quadratic,
+    // total[d] is the cost of moving d, steps past the end of the table cost
+    // as much as the last one
+    Table { steps: Vec<i64>, total: Vec<i64> },
 }
 
-fn sum_steps(start: i32, end: i32) -> i32 {
-    (1..=(start - end).abs()).fold(0, |sum, n| sum + n)
+impl FuelCost {
+    // Builds a cost from what each successive step costs
+    fn table(steps: Vec<i64>) -> Self {
+        if steps.is_empty() {
+            panic!("A cost table needs at least one step");
+        }
+        let mut total = Vec::with_capacity(steps.len() + 1);
+        total.push(0);
+        for step in &steps {
+            total.push(total.last().unwrap() + step);
+        }
+
+        FuelCost::Table { steps, total }
+    }
+
+    fn parse(spec: &str) -> Self {
+        match spec {
+            "linear" => FuelCost::Linear,
+            "triangular" => FuelCost::Triangular,
+            "quadratic" => FuelCost::Quadratic,
+            _ => match spec.strip_prefix("table:") {
+                Some(steps) => FuelCost::table(
+                    steps
+                        .split(',')
+                        .map(|n| n.parse::<i64>().unwrap())
+                        .collect(),
+                ),
+                None => panic!("Unknown fuel cost {}", spec),
+            },
+        }
+    }
+
+    fn cost(&self, distance: i64) -> i64 {
+        match self {
+            FuelCost::Linear => distance,
+            FuelCost::Triangular => distance * (distance + 1) / 2,
+            FuelCost::Quadratic => distance * distance,
+            FuelCost::Table { steps, total } => {
+                let last = steps.len() as i64;
+                if distance <= last {
+                    total[distance as usize]
+                } else {
+                    total[steps.len()] + (distance - last) * steps[steps.len() - 1]
+                }
+            }
+        }
+    }
+
+    // The total over all crabs is convex whenever no step is cheaper than the
+    // one before it, which lets the optimiser binary search. A negative first
+    // step would make moving away from a crab cheaper than staying on it.
+    fn is_convex(&self) -> bool {
+        match self {
+            FuelCost::Table { steps, .. } => {
+                steps[0] >= 0 && steps.windows(2).all(|pair| pair[0] <= pair[1])
+            }
+            _ => true,
+        }
+    }
+}
+
+struct Crabs {
+    sorted: Vec<i64>,
+    // each distinct position with how many crabs are there
+    runs: Vec<(i64, usize)>,
+    // prefix[i] is the sum of the first i sorted positions, prefix_squares
+    // the same for their squares
+    prefix: Vec<i128>,
+    prefix_squares: Vec<i128>,
 }
 
-fn calc_fuel_p2(positions: &Vec<i32>, target: i32) -> i32 {
-    positions
-        .iter()
-        .fold(0, |sum, n| sum + sum_steps(target, *n))
+impl Crabs {
+    fn new(positions: &[i64]) -> Self {
+        let mut sorted = positions.to_vec();
+        sorted.sort_unstable();
+
+        let mut runs: Vec<(i64, usize)> = Vec::new();
+        for position in &sorted {
+            match runs.last_mut() {
+                Some((last, count)) if last == position => *count += 1,
+                _ => runs.push((*position, 1)),
+            }
+        }
+
+        let mut prefix = vec![0i128; sorted.len() + 1];
+        let mut prefix_squares = vec![0i128; sorted.len() + 1];
+        for (i, position) in sorted.iter().enumerate() {
+            let position = *position as i128;
+            prefix[i + 1] = prefix[i] + position;
+            prefix_squares[i + 1] = prefix_squares[i] + position * position;
+        }
+
+        Crabs {
+            sorted,
+            runs,
+            prefix,
+            prefix_squares,
+        }
+    }
+
+    // Sum of |position - target| in O(log n)
+    fn sum_distances(&self, target: i64) -> i128 {
+        let n = self.sorted.len();
+        let left = self.sorted.partition_point(|position| *position <= target);
+        let target = target as i128;
+
+        target * left as i128 - self.prefix[left] + (self.prefix[n] - self.prefix[left])
+            - target * (n - left) as i128
+    }
+
+    // Sum of (position - target)^2 in O(1)
+    fn sum_squared_distances(&self, target: i64) -> i128 {
+        let n = self.sorted.len();
+        let target = target as i128;
+
+        self.prefix_squares[n] - 2 * target * self.prefix[n] + n as i128 * target * target
+    }
+
+    // Sum of a table cost. Past the end of the table each step costs the
+    // same, so crabs further away than that add up like linear costs from the
+    // prefix sums. Only the positions within reach of the table are looked up
+    // one by one, which is O(log n + steps).
+    fn sum_table_costs(&self, cost: &FuelCost, steps: &[i64], total: &[i64], target: i64) -> i128 {
+        let n = self.sorted.len();
+        let reach = steps.len() as i64;
+        let last = steps[steps.len() - 1] as i128;
+        let left = self
+            .sorted
+            .partition_point(|position| *position < target - reach);
+        let right = self
+            .sorted
+            .partition_point(|position| *position <= target + reach);
+        let t = target as i128;
+
+        // a crab d > reach away costs total[reach] + (d - reach) * last
+        let far = (left + n - right) as i128;
+        let far_distances = t * left as i128 - self.prefix[left]
+            + (self.prefix[n] - self.prefix[right])
+            - t * (n - right) as i128;
+        let far_costs =
+            far * (total[steps.len()] as i128 - reach as i128 * last) + last * far_distances;
+
+        let near_start = self
+            .runs
+            .partition_point(|(position, _)| *position < target - reach);
+        let near_costs: i128 = self.runs[near_start..]
+            .iter()
+            .take_while(|(position, _)| *position <= target + reach)
+            .map(|(position, count)| cost.cost((position - target).abs()) as i128 * *count as i128)
+            .sum();
+
+        far_costs + near_costs
+    }
+
+    fn total(&self, cost: &FuelCost, target: i64) -> i64 {
+        let total = match cost {
+            FuelCost::Linear => self.sum_distances(target),
+            FuelCost::Triangular => {
+                (self.sum_squared_distances(target) + self.sum_distances(target)) / 2
+            }
+            FuelCost::Quadratic => self.sum_squared_distances(target),
+            FuelCost::Table { steps, total } => self.sum_table_costs(cost, steps, total, target),
+        };
+
+        i64::try_from(total).unwrap_or_else(|_| {
+            panic!(
+                "Aligning at {} costs {}, more than fits in i64",
+                target, total
+            )
+        })
+    }
+
+    // A table total is linear in the target wherever no crab is within reach
+    // of the table's end, so its minimum is at the edge of one of those
+    // stretches or close enough to a crab to use the table. These are the
+    // targets that covers, lowest first.
+    fn table_candidates(&self, reach: i64, low: i64, high: i64) -> Vec<i64> {
+        let mut targets: Vec<i64> = self
+            .runs
+            .iter()
+            .flat_map(|(position, _)| position - reach - 1..=position + reach + 1)
+            .chain([low, high])
+            .filter(|target| (low..=high).contains(target))
+            .collect();
+        targets.sort_unstable();
+        targets.dedup();
+        targets
+    }
+
+    // Finds the cheapest position between the outermost crabs, returning the
+    // lowest optimal position with its fuel
+    fn align(&self, cost: &FuelCost) -> (i64, i64) {
+        let low = self.sorted[0];
+        let high = self.sorted[self.sorted.len() - 1];
+
+        match cost {
+            // the median minimises the sum of distances
+            FuelCost::Linear => {
+                let median = self.sorted[(self.sorted.len() - 1) / 2];
+                (median, self.total(cost, median))
+            }
+            // the minimum is within half a step of the mean, so the best whole
+            // position is one of the few around it
+            FuelCost::Triangular => {
+                let len = self.sorted.len() as i128;
+                let floor = self.prefix[self.sorted.len()].div_euclid(len) as i64;
+                self.cheapest(cost, (floor - 1).max(low)..=(floor + 2).min(high))
+            }
+            _ if cost.is_convex() => self.convex_search(cost, low, high),
+            FuelCost::Table { steps, .. } => {
+                let targets = self.table_candidates(steps.len() as i64, low, high);
+                self.cheapest(cost, targets.into_iter())
+            }
+            _ => self.cheapest(cost, low..=high),
+        }
+    }
+
+    // Binary searches for where the convex total stops falling
+    fn convex_search(&self, cost: &FuelCost, mut low: i64, mut high: i64) -> (i64, i64) {
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.total(cost, mid + 1) < self.total(cost, mid) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        (low, self.total(cost, low))
+    }
+
+    fn cheapest(&self, cost: &FuelCost, targets: impl Iterator<Item = i64>) -> (i64, i64) {
+        targets
+            .map(|target| (target, self.total(cost, target)))
+            .min_by_key(|(_, fuel)| *fuel)
+            .unwrap()
+    }
 }
 
 fn main() -> Result<()> {
     let text = read_text("7/input.txt")?;
 
-    let positions: Vec<i32> = text.split(",").map(|n| n.parse::<i32>().unwrap()).collect();
+    let positions: Vec<i64> = text
+        .trim()
+        .split(',')
+        .map(|n| n.parse::<i64>().unwrap())
+        .collect();
+    let crabs = Crabs::new(&positions);
+
+    let (position, fuel) = crabs.align(&FuelCost::Linear);
+    println!("{} at position {}", fuel, position);
 
-    let avg = positions.iter().fold(0, |sum, n| sum + *n as i32) / positions.len() as i32;
+    let (position, fuel) = crabs.align(&FuelCost::Triangular);
+    println!("{} at position {}", fuel, position);
 
-    let mut lowest_fuel = i32::MAX;
-    let count = 800;
-    let mut start_count = -count / 2;
-    if avg + start_count < 0 {
-        start_count = 0;
+    // --cost linear, triangular, quadratic or table:1,2,3
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cost" => {
+                let cost = FuelCost::parse(&args.next().expect("--cost needs a cost function"));
+                let (position, fuel) = crabs.align(&cost);
+                println!("{} at position {}", fuel, position);
+            }
+            _ => panic!("Unknown argument {}", arg),
+        }
     }
-    for i in start_count..count {
-        let fuel = calc_fuel(&positions, i);
-        lowest_fuel = cmp::min(lowest_fuel, fuel);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: [i64; 10] = [16, 1, 2, 0, 4, 2, 7, 1, 2, 14];
+
+    fn brute_force(positions: &[i64], cost: &FuelCost) -> (i64, i64) {
+        let low = *positions.iter().min().unwrap();
+        let high = *positions.iter().max().unwrap();
+        (low..=high)
+            .map(|target| {
+                let fuel = positions
+                    .iter()
+                    .map(|position| cost.cost((position - target).abs()))
+                    .sum();
+                (target, fuel)
+            })
+            .min_by_key(|(_, fuel)| *fuel)
+            .unwrap()
     }
-    println!("{}", lowest_fuel);
 
-    lowest_fuel = i32::MAX;
-    for i in start_count..count {
-        let fuel = calc_fuel_p2(&positions, i);
-        lowest_fuel = cmp::min(lowest_fuel, fuel);
+    #[test]
+    fn test_align() {
+        let crabs = Crabs::new(&EXAMPLE);
+        assert_eq!(crabs.align(&FuelCost::Linear), (2, 37));
+        assert_eq!(crabs.align(&FuelCost::Triangular), (5, 168));
+        assert_eq!(crabs.convex_search(&FuelCost::Linear, 0, 16), (2, 37));
+        assert_eq!(crabs.convex_search(&FuelCost::Triangular, 0, 16), (5, 168));
     }
 
-    println!("{}", lowest_fuel);
+    #[test]
+    fn test_costs_match_brute_force() {
+        let spread = [5000, 5003, 5010, 2000, 2000, 17];
+        let costs = [
+            FuelCost::Linear,
+            FuelCost::Triangular,
+            FuelCost::Quadratic,
+            FuelCost::table(vec![1, 1, 4, 9]),
+            // cheaper later steps make the total non-convex
+            FuelCost::table(vec![10, 1]),
+            // non-decreasing, but a first step below zero isn't convex either
+            FuelCost::table(vec![-5, 1]),
+        ];
+        for positions in [&EXAMPLE[..], &spread[..], &[3, 6, 12, 20][..]] {
+            let crabs = Crabs::new(positions);
+            for cost in &costs {
+                let (position, fuel) = crabs.align(cost);
+                let expected = brute_force(positions, cost);
+                assert_eq!(fuel, expected.1, "{:?}", cost);
+                assert_eq!(crabs.total(cost, position), fuel);
+            }
+        }
+    }
 
-    Ok(())
+    #[test]
+    fn test_table_costs_far_apart() {
+        // crabs spread much further than the table reaches, and bunched up
+        let positions = [0, 3, 3, 3, 40, 41, 1000, 1003, 5000, 5000, 5000];
+        let crabs = Crabs::new(&positions);
+        for cost in [
+            FuelCost::table(vec![5, 1, 1, 2]),
+            FuelCost::table(vec![1, 3, 2]),
+            FuelCost::table(vec![7]),
+        ] {
+            for target in [-10, 0, 2, 3, 20, 41, 45, 999, 1001, 4998, 5000, 6000] {
+                let expected: i64 = positions
+                    .iter()
+                    .map(|position| cost.cost((position - target).abs()))
+                    .sum();
+                assert_eq!(
+                    crabs.total(&cost, target),
+                    expected,
+                    "{:?} at {}",
+                    cost,
+                    target
+                );
+            }
+            assert_eq!(
+                crabs.align(&cost),
+                brute_force(&positions, &cost),
+                "{:?}",
+                cost
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "more than fits in i64")]
+    fn test_total_overflow() {
+        let crabs = Crabs::new(&[i64::MIN / 2, i64::MAX / 2]);
+        crabs.total(&FuelCost::Quadratic, 0);
+    }
+
+    #[test]
+    fn test_table_cost() {
+        let cost = FuelCost::parse("table:1,2,5");
+        assert_eq!(
+            (0..6).map(|d| cost.cost(d)).collect::<Vec<i64>>(),
+            vec![0, 1, 3, 8, 13, 18]
+        );
+        assert!(cost.is_convex());
+        assert!(!FuelCost::table(vec![2, 1]).is_convex());
+    }
 }
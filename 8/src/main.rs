@@ -1,429 +1,873 @@
-use core::hash::Hash;
-use std::collections::{HashMap, HashSet};
-use std::fmt::{Debug, Display};
+use std::fmt;
 use std::io::Result;
 
 use read_input::read_text;
 
-fn pattern_to_set(pattern: &str) -> HashSet<String> {
-    let mut set = HashSet::new();
-    for ch in pattern.chars() {
-        set.insert(ch.to_string());
+// Patterns are bitmasks over a display's segments, so a display can have at
+// most this many
+const MAX_SEGMENTS: usize = 32;
+
+// The standard seven segment digits, segments a-g
+const SEVEN_SEGMENT: &str = "segments abcdefg
+0 abcefg
+1 cf
+2 acdeg
+3 acdfg
+4 bcdf
+5 abdfg
+6 abdefg
+7 acf
+8 abcdefg
+9 abcdfg";
+
+// Which segments a display has and which of them light up for each symbol.
+// Wires carry the same names as the segments they are meant to drive.
+struct DisplayDefinition {
+    segments: Vec<char>,
+    symbols: Vec<(String, u32)>,
+}
+
+impl DisplayDefinition {
+    // Parses a "segments <names>" line followed by one "<symbol> <segments>"
+    // line per symbol
+    fn parse(text: &str) -> std::result::Result<Self, String> {
+        let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+        let segments: Vec<char> = match lines.next().and_then(|line| line.strip_prefix("segments "))
+        {
+            Some(names) => names.trim().chars().collect(),
+            None => return Err("the first line has to be \"segments <names>\"".to_string()),
+        };
+        if segments.is_empty() || segments.len() > MAX_SEGMENTS {
+            return Err(format!("a display needs 1 to {} segments", MAX_SEGMENTS));
+        }
+        if let Some(name) = segments
+            .iter()
+            .enumerate()
+            .find(|(i, name)| segments[..*i].contains(name))
+            .map(|(_, name)| name)
+        {
+            return Err(format!("segment {} is named twice", name));
+        }
+
+        let mut display = DisplayDefinition {
+            segments,
+            symbols: Vec::new(),
+        };
+        for line in lines {
+            let (symbol, lit) = match line.trim().split_once(' ') {
+                Some(parts) => parts,
+                None => return Err(format!("expected \"<symbol> <segments>\", found {}", line)),
+            };
+            let mask = display.pattern_to_mask(lit.trim())?;
+            if let Some((other, _)) = display.symbols.iter().find(|(_, other)| *other == mask) {
+                return Err(format!("{} and {} light the same segments", other, symbol));
+            }
+            display.symbols.push((symbol.to_string(), mask));
+        }
+        if display.symbols.is_empty() {
+            return Err("a display needs at least one symbol".to_string());
+        }
+
+        Ok(display)
     }
 
-    set
-}
+    fn seven_segment() -> Self {
+        DisplayDefinition::parse(SEVEN_SEGMENT).unwrap()
+    }
 
-fn get_from_number_map<'a>(
-    map: &'a HashMap<i32, HashSet<String>>,
-    key: i32,
-    patterns: &Vec<HashSet<String>>,
-) -> &'a HashSet<String> {
-    if let Some(pattern) = map.get(&key) {
-        pattern
-    } else {
-        panic!("Line: {:?} didn't have a pattern for 4", patterns);
+    fn all_segments(&self) -> u32 {
+        u32::MAX >> (MAX_SEGMENTS - self.segments.len())
     }
-}
 
-fn insert_once<K, V>(map: &mut HashMap<K, V>, key: K, value: V)
-where
-    K: Eq + Hash + Debug + Display,
-    V: Debug,
-{
-    if map.contains_key(&key) {
-        panic!("Already have {} for {:?}", key, map);
+    fn pattern_to_mask(&self, pattern: &str) -> std::result::Result<u32, String> {
+        if pattern.is_empty() {
+            return Err("empty pattern".to_string());
+        }
+
+        pattern.chars().try_fold(0, |mask, ch| {
+            let wire = match self.segments.iter().position(|name| *name == ch) {
+                Some(i) => 1 << i,
+                None => return Err(format!("unknown wire {} in {}", ch, pattern)),
+            };
+            if mask & wire != 0 {
+                return Err(format!("wire {} repeated in {}", ch, pattern));
+            }
+            Ok(mask | wire)
+        })
     }
 
-    map.insert(key, value);
+    fn mask_to_pattern(&self, mask: u32) -> String {
+        self.segments
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| mask & 1 << i != 0)
+            .map(|(_, name)| name)
+            .collect()
+    }
+
+    fn symbol_for(&self, mask: u32) -> Option<usize> {
+        self.symbols.iter().position(|(_, lit)| *lit == mask)
+    }
+
+    // Symbols that are the only ones lighting their number of segments, so
+    // they can be picked out by length alone
+    fn has_unique_length(&self, symbol: usize) -> bool {
+        let lit = self.symbols[symbol].1.count_ones();
+        self.symbols
+            .iter()
+            .filter(|(_, other)| other.count_ones() == lit)
+            .count()
+            == 1
+    }
 }
 
-fn solve_digit_for_g(
-    signal_patterns: &Vec<HashSet<String>>,
-    transposition_map: &mut HashMap<String, String>,
-    number_to_digits: &mut HashMap<i32, HashSet<String>>,
-) {
-    for pattern in signal_patterns {
-        match pattern.len() {
-            6 => {
-                // can be a 0 a 6 or a 9
-                let mut count = 0;
-                let mut digit_not_in_the_pattern = None;
-                for digit in pattern {
-                    let digits_for_four = get_from_number_map(number_to_digits, 4, signal_patterns);
-                    let digit_for_a = transposition_map.get("a").unwrap();
-                    if digit == digit_for_a || digits_for_four.contains(digit) {
-                        count += 1;
-                    } else {
-                        digit_not_in_the_pattern = Some(digit.to_owned());
-                    }
-                }
+struct Entry {
+    signals: Vec<u32>,
+    outputs: Vec<u32>,
+}
 
-                // we must have a 9
-                if count == 5 {
-                    let g_digit = digit_not_in_the_pattern.unwrap();
-                    insert_once(transposition_map, "g".to_string(), g_digit.clone());
-                    let mut new_pattern = pattern.clone();
-                    new_pattern.insert(g_digit);
-                    number_to_digits.insert(9, new_pattern);
+#[derive(Debug, PartialEq)]
+struct Decoded {
+    // wiring[wire] is the segment that wire actually drives
+    wiring: Vec<usize>,
+    // indexes into the display's symbols
+    outputs: Vec<usize>,
+    // the output symbols' names joined together
+    reading: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum Diagnosis {
+    Solved(Decoded),
+    // the wirings that fit the line, up to MAX_CANDIDATES, and whether there
+    // are or may be more past those
+    Ambiguous {
+        candidates: Vec<Decoded>,
+        more: bool,
+    },
+    // a smallest set of patterns that no wiring can satisfy together
+    Inconsistent {
+        signals: Vec<String>,
+        outputs: Vec<String>,
+    },
+    Malformed(String),
+    // the search ran out of steps before it could tell which of the above
+    // the line is
+    Undecided {
+        steps: usize,
+    },
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Diagnosis::Solved(decoded) => write!(f, "solved as {}", decoded.reading),
+            Diagnosis::Ambiguous { candidates, more } => {
+                write!(
+                    f,
+                    "ambiguous, {}{} wirings:",
+                    if *more { "at least " } else { "" },
+                    candidates.len()
+                )?;
+                for candidate in candidates.iter().take(SHOWN_CANDIDATES) {
+                    write!(f, " {:?} reads {};", candidate.wiring, candidate.reading)?;
+                }
+                if candidates.len() > SHOWN_CANDIDATES || *more {
+                    write!(f, " ...")?;
                 }
+                Ok(())
+            }
+            Diagnosis::Inconsistent { signals, outputs } => write!(
+                f,
+                "inconsistent, no wiring fits signals [{}] with outputs [{}]",
+                signals.join(" "),
+                outputs.join(" ")
+            ),
+            Diagnosis::Malformed(reason) => write!(f, "malformed, {}", reason),
+            Diagnosis::Undecided { steps } => {
+                write!(f, "undecided, gave up after {} search steps", steps)
             }
-            _ => {}
         }
     }
 }
 
-fn solve_digit_for_e(
-    signal_patterns: &Vec<HashSet<String>>,
-    transposition_map: &mut HashMap<String, String>,
-    number_to_digits: &mut HashMap<i32, HashSet<String>>,
-) {
-    for pattern in signal_patterns {
-        match pattern.len() {
-            6 => {
-                // can be a 0 a 6 or a 9
-                let mut digit_not_in_the_pattern = None;
-                for digit in pattern {
-                    let digits_for_nine = get_from_number_map(number_to_digits, 9, signal_patterns);
-                    if !digits_for_nine.contains(digit) {
-                        digit_not_in_the_pattern = Some(digit.to_owned());
-                        break;
-                    }
-                }
+fn parse_entry(display: &DisplayDefinition, line: &str) -> std::result::Result<Entry, String> {
+    let parts: Vec<&str> = line.split(" | ").collect();
+    if parts.len() != 2 {
+        return Err("expected signal patterns and outputs split by \" | \"".to_string());
+    }
 
-                if let Some(digit) = digit_not_in_the_pattern {
-                    insert_once(transposition_map, "e".to_owned(), digit);
-                    break;
-                }
+    let masks = |part: &str| {
+        part.split_whitespace()
+            .map(|pattern| display.pattern_to_mask(pattern))
+            .collect::<std::result::Result<Vec<u32>, String>>()
+    };
+
+    Ok(Entry {
+        signals: masks(parts[0])?,
+        outputs: masks(parts[1])?,
+    })
+}
+
+fn rewire(mask: u32, wiring: &[usize]) -> u32 {
+    (0..wiring.len())
+        .filter(|wire| mask & 1 << wire != 0)
+        .fold(0, |rewired, wire| rewired | 1 << wiring[wire])
+}
+
+// candidates[wire] is the set of segments the wire could drive. A pattern
+// lighting n wires can only be one of the symbols with n segments, so its
+// wires must drive segments those symbols use and the other wires must drive
+// segments at least one of them leaves off.
+fn wire_candidates(display: &DisplayDefinition, entry: &Entry) -> Vec<u32> {
+    let all = display.all_segments();
+    let mut candidates = vec![all; display.segments.len()];
+    for pattern in entry.signals.iter().chain(entry.outputs.iter()) {
+        let lit = pattern.count_ones();
+        let (used, unused) = display
+            .symbols
+            .iter()
+            .filter(|(_, symbol)| symbol.count_ones() == lit)
+            .fold((0u32, 0u32), |(used, unused), (_, symbol)| {
+                (used | symbol, unused | !symbol & all)
+            });
+
+        for (wire, candidate) in candidates.iter_mut().enumerate() {
+            if pattern & 1 << wire != 0 {
+                *candidate &= used;
+            } else {
+                *candidate &= unused;
             }
-            _ => {}
         }
     }
 
-    if !transposition_map.contains_key("e") {
-        panic!("Did not find e in 6 or 0 when it should have");
-    }
+    candidates
 }
 
-fn solve_digit_for_f(
-    signal_patterns: &Vec<HashSet<String>>,
-    transposition_map: &mut HashMap<String, String>,
-    number_to_digits: &mut HashMap<i32, HashSet<String>>,
-) {
-    for pattern in signal_patterns {
-        match pattern.len() {
-            5 => {
-                // can be 2, 3 or 5
-                let mut digit_for_f = None;
-                {
-                    let digit_for_a = transposition_map.get("a").unwrap();
-                    let digit_for_c = transposition_map.get("c").unwrap();
-                    let digit_for_d = transposition_map.get("d").unwrap();
-                    let digit_for_e = transposition_map.get("e").unwrap();
-                    let digit_for_g = transposition_map.get("g").unwrap();
-
-                    // it's a 2, let's insert it
-                    if pattern.contains(digit_for_a)
-                        && pattern.contains(digit_for_c)
-                        && pattern.contains(digit_for_d)
-                        && pattern.contains(digit_for_e)
-                        && pattern.contains(digit_for_g)
-                    {
-                        insert_once(number_to_digits, 2, pattern.clone());
-                    }
-
-                    // it's a 3
-                    if !pattern.contains(digit_for_e) && pattern.contains(digit_for_c) {
-                        for digit in pattern {
-                            // digit must be f
-                            if digit != digit_for_a
-                                && digit != digit_for_c
-                                && digit != digit_for_d
-                                && digit != digit_for_g
-                            {
-                                digit_for_f = Some(digit.to_owned());
-                            }
-                        }
-
-                        insert_once(number_to_digits, 3, pattern.clone());
-                    }
-                };
-
-                if let Some(digit_for_f) = digit_for_f {
-                    insert_once(transposition_map, "f".to_owned(), digit_for_f);
-                }
-            }
+// The symbols `pattern` could still show, given the segments its wires
+// among `known_wires` drive
+fn possible_symbols<'a>(
+    display: &'a DisplayDefinition,
+    pattern: u32,
+    wiring: &[usize],
+    known_wires: u32,
+) -> impl Iterator<Item = usize> + 'a {
+    let known_segments = rewire(known_wires, wiring);
+    let lit = rewire(pattern & known_wires, wiring);
+    display
+        .symbols
+        .iter()
+        .enumerate()
+        .filter(move |(_, (_, symbol))| {
+            symbol.count_ones() == pattern.count_ones() && symbol & known_segments == lit
+        })
+        .map(|(i, _)| i)
+}
+
+// With the first `assigned` wires placed, every pattern still has to match
+// some symbol of its length on the segments those wires drive, and no two
+// signals can be down to the same one symbol
+fn partial_fits(
+    display: &DisplayDefinition,
+    entry: &Entry,
+    wiring: &[usize],
+    assigned: usize,
+) -> bool {
+    let known_wires = u32::MAX >> (MAX_SEGMENTS - assigned);
+
+    let mut taken = vec![false; display.symbols.len()];
+    for pattern in &entry.signals {
+        let mut symbols = possible_symbols(display, *pattern, wiring, known_wires);
+        match (symbols.next(), symbols.next()) {
+            (None, _) => return false,
+            (Some(symbol), None) if taken[symbol] => return false,
+            (Some(symbol), None) => taken[symbol] = true,
             _ => {}
         }
     }
 
-    if !transposition_map.contains_key("f") {
-        panic!("Could not find f in 3: {:?}", signal_patterns);
+    entry.outputs.iter().all(|pattern| {
+        possible_symbols(display, *pattern, wiring, known_wires)
+            .next()
+            .is_some()
+    })
+}
+
+// A wiring fits when every signal pattern shows a different symbol and every
+// output pattern shows some symbol
+fn fits(display: &DisplayDefinition, entry: &Entry, wiring: &[usize]) -> bool {
+    let mut seen = vec![false; display.symbols.len()];
+    for pattern in &entry.signals {
+        match display.symbol_for(rewire(*pattern, wiring)) {
+            Some(symbol) if !seen[symbol] => seen[symbol] = true,
+            _ => return false,
+        }
     }
+
+    entry
+        .outputs
+        .iter()
+        .all(|pattern| display.symbol_for(rewire(*pattern, wiring)).is_some())
 }
 
-fn solve_digit_for_b(
-    signal_patterns: &Vec<HashSet<String>>,
-    transposition_map: &mut HashMap<String, String>,
-    number_to_digits: &mut HashMap<i32, HashSet<String>>,
-) {
-    for pattern in signal_patterns {
-        match pattern.len() {
-            5 => {
-                // can be 2, 3 or 5
-                let mut digit_for_b = None;
-                {
-                    let digit_for_a = transposition_map.get("a").unwrap();
-                    let digit_for_c = transposition_map.get("c").unwrap();
-                    let digit_for_d = transposition_map.get("d").unwrap();
-                    let digit_for_e = transposition_map.get("e").unwrap();
-                    let digit_for_f = transposition_map.get("f").unwrap();
-                    let digit_for_g = transposition_map.get("g").unwrap();
-
-                    // it's a 5
-                    if !pattern.contains(digit_for_e) && !pattern.contains(digit_for_c) {
-                        for digit in pattern {
-                            // digit must be b
-                            if digit != digit_for_a
-                                && digit != digit_for_d
-                                && digit != digit_for_f
-                                && digit != digit_for_g
-                            {
-                                digit_for_b = Some(digit.to_owned());
-                            }
-                        }
-
-                        insert_once(number_to_digits, 5, pattern.clone());
-                    }
-                };
-
-                if let Some(digit_for_f) = digit_for_b {
-                    insert_once(transposition_map, "b".to_owned(), digit_for_f);
+struct Search<'a> {
+    display: &'a DisplayDefinition,
+    entry: &'a Entry,
+    candidates: Vec<u32>,
+    wiring: Vec<usize>,
+    found: Vec<Vec<usize>>,
+    limit: usize,
+    // how many more wires the search can try before it gives up
+    steps: &'a mut usize,
+    gave_up: bool,
+}
+
+impl Search<'_> {
+    // Assigns a segment to each wire in turn, backtracking as soon as a
+    // pattern can no longer match any symbol, and collects up to `limit`
+    // wirings that fit
+    fn assign(&mut self, wire: usize, taken: u32) {
+        if *self.steps == 0 {
+            self.gave_up = true;
+            return;
+        }
+        *self.steps -= 1;
+
+        if wire == self.wiring.len() {
+            if fits(self.display, self.entry, &self.wiring) {
+                self.found.push(self.wiring.clone());
+            }
+            return;
+        }
+
+        for segment in 0..self.wiring.len() {
+            if self.found.len() >= self.limit || self.gave_up {
+                return;
+            }
+            if self.candidates[wire] & 1 << segment != 0 && taken & 1 << segment == 0 {
+                self.wiring[wire] = segment;
+                if partial_fits(self.display, self.entry, &self.wiring, wire + 1) {
+                    self.assign(wire + 1, taken | 1 << segment);
                 }
             }
-            _ => {}
         }
     }
+}
+
+// The wirings a search found, and whether it ran out of steps before it
+// either tried every wiring or found as many as it was after
+struct Found {
+    wirings: Vec<Vec<usize>>,
+    gave_up: bool,
+}
 
-    if !transposition_map.contains_key("b") {
-        panic!("Could not find b in 5: {:?}", signal_patterns);
+impl Found {
+    // Whether the search showed that no wiring fits
+    fn none_fit(&self) -> bool {
+        self.wirings.is_empty() && !self.gave_up
     }
 }
 
-fn find_six(
-    signal_patterns: &Vec<HashSet<String>>,
-    number_to_digits: &mut HashMap<i32, HashSet<String>>,
-) {
-    for pattern in signal_patterns {
-        match pattern.len() {
-            6 => {
-                let is_six = {
-                    let mut is_six = false;
-                    let digits_for_four = get_from_number_map(number_to_digits, 4, signal_patterns);
-                    let digits_for_one = get_from_number_map(number_to_digits, 1, signal_patterns);
-                    // get b & d from 4
-                    let remaining_digits: Vec<&String> = digits_for_four
-                        .iter()
-                        .filter(|digit| !digits_for_one.contains(*digit))
-                        .collect();
-
-                    let mut is_not_nine = false;
-                    // verify this number is not a 9
-                    let digits_for_nine = get_from_number_map(number_to_digits, 9, signal_patterns);
-                    for digit in pattern {
-                        if !digits_for_nine.contains(digit) {
-                            is_not_nine = true;
-                        }
-                    }
-
-                    if is_not_nine {
-                        let digit_contains_b_and_d = remaining_digits
-                            .iter()
-                            .fold(true, |result, digit| result && pattern.contains(*digit));
-
-                        // is not 9, and contains b & d, so it must be six and not zero
-                        if digit_contains_b_and_d {
-                            is_six = true;
-                        }
-                    }
-
-                    is_six
-                };
-
-                if is_six {
-                    insert_once(number_to_digits, 6, pattern.clone());
-                }
-            }
-            _ => {}
+fn wirings(display: &DisplayDefinition, entry: &Entry, limit: usize, steps: &mut usize) -> Found {
+    // signals all show different symbols, so two of the same never fit
+    if entry
+        .signals
+        .iter()
+        .enumerate()
+        .any(|(i, signal)| entry.signals[..i].contains(signal))
+    {
+        return Found {
+            wirings: Vec::new(),
+            gave_up: false,
+        };
+    }
+
+    let mut search = Search {
+        display,
+        entry,
+        candidates: wire_candidates(display, entry),
+        wiring: vec![0; display.segments.len()],
+        found: Vec::new(),
+        limit,
+        steps,
+        gave_up: false,
+    };
+    search.assign(0, 0);
+    Found {
+        wirings: search.found,
+        gave_up: search.gave_up,
+    }
+}
+
+fn read(display: &DisplayDefinition, entry: &Entry, wiring: Vec<usize>) -> Decoded {
+    let outputs: Vec<usize> = entry
+        .outputs
+        .iter()
+        .map(|pattern| display.symbol_for(rewire(*pattern, &wiring)).unwrap())
+        .collect();
+    let reading = outputs
+        .iter()
+        .map(|symbol| display.symbols[*symbol].0.as_str())
+        .collect();
+
+    Decoded {
+        wiring,
+        outputs,
+        reading,
+    }
+}
+
+// How many wirings an ambiguous line keeps, and how many of those its report
+// lists
+const MAX_CANDIDATES: usize = 100;
+const SHOWN_CANDIDATES: usize = 5;
+
+// How many wires a line's search tries before giving up on it, and again for
+// narrowing down the conflict on an inconsistent line
+const MAX_SEARCH_STEPS: usize = 200_000;
+
+// Conflicts are usually between a couple of patterns, so the smallest
+// conflicting set of up to this many patterns is looked for directly
+const SMALL_CONFLICT: usize = 3;
+
+fn split_patterns(entry: &Entry, chosen: &[usize]) -> Entry {
+    let signals = entry.signals.len();
+    Entry {
+        signals: chosen
+            .iter()
+            .filter(|i| **i < signals)
+            .map(|i| entry.signals[*i])
+            .collect(),
+        outputs: chosen
+            .iter()
+            .filter(|i| **i >= signals)
+            .map(|i| entry.outputs[*i - signals])
+            .collect(),
+    }
+}
+
+// Tries every set of `size` patterns, signals numbered before outputs, and
+// returns the first one no wiring fits
+fn conflict_of_size(
+    display: &DisplayDefinition,
+    entry: &Entry,
+    size: usize,
+    chosen: &mut Vec<usize>,
+    steps: &mut usize,
+) -> Option<Entry> {
+    if chosen.len() == size {
+        let subset = split_patterns(entry, chosen);
+        return if wirings(display, &subset, 1, steps).none_fit() {
+            Some(subset)
+        } else {
+            None
+        };
+    }
+
+    let start = chosen.last().map_or(0, |last| last + 1);
+    for i in start..entry.signals.len() + entry.outputs.len() {
+        chosen.push(i);
+        let conflict = conflict_of_size(display, entry, size, chosen, steps);
+        chosen.pop();
+        if conflict.is_some() {
+            return conflict;
         }
     }
+
+    None
 }
 
-fn find_zero(
-    signal_patterns: &Vec<HashSet<String>>,
-    transposition_map: &mut HashMap<String, String>,
-    number_to_digits: &mut HashMap<i32, HashSet<String>>,
-) {
-    for pattern in signal_patterns {
-        match pattern.len() {
-            6 => {
-                let is_zero = {
-                    let digits_for_nine = get_from_number_map(number_to_digits, 9, signal_patterns);
-                    let digits_for_six = get_from_number_map(number_to_digits, 6, signal_patterns);
-
-                    let is_nine = digits_for_nine
-                        .iter()
-                        .fold(true, |result, digit| result && pattern.contains(digit));
-
-                    let is_six = digits_for_six
-                        .iter()
-                        .fold(true, |result, digit| result && pattern.contains(digit));
-
-                    // must be zero
-                    !is_six && !is_nine
-                };
-
-                if is_zero {
-                    number_to_digits.insert(0, pattern.clone());
-
-                    let digits_for_six = get_from_number_map(number_to_digits, 6, signal_patterns);
-
-                    for digit in pattern {
-                        // six doesnt contain a digit from zero, must be c
-                        if !digits_for_six.contains(digit) {
-                            insert_once(transposition_map, "c".to_owned(), digit.to_owned());
-                        }
-                    }
-
-                    for digit in digits_for_six {
-                        // zero doesnt contain a digit from 6, must be d
-                        if !pattern.contains(digit) {
-                            insert_once(transposition_map, "d".to_owned(), digit.to_owned());
-                        }
-                    }
-                }
+// Drops patterns one at a time, keeping each one out if the rest still has no
+// wiring, which leaves a set where every pattern is part of the conflict.
+// Outputs go first so a conflict among the signals alone is preferred.
+fn minimal_conflict(display: &DisplayDefinition, entry: &Entry, steps: &mut usize) -> Entry {
+    let mut rest = Entry {
+        signals: entry.signals.clone(),
+        outputs: entry.outputs.clone(),
+    };
+
+    for outputs in [true, false] {
+        let mut i = 0;
+        loop {
+            let patterns = if outputs {
+                &mut rest.outputs
+            } else {
+                &mut rest.signals
+            };
+            if i >= patterns.len() {
+                break;
             }
-            _ => {}
+
+            let removed = patterns.remove(i);
+            if wirings(display, &rest, 1, steps).none_fit() {
+                continue;
+            }
+
+            let patterns = if outputs {
+                &mut rest.outputs
+            } else {
+                &mut rest.signals
+            };
+            patterns.insert(i, removed);
+            i += 1;
         }
     }
+
+    rest
+}
+
+// Once the steps run out nothing more can be dropped, so the conflict may be
+// larger than it has to be, but no wiring fits it all the same
+fn conflicting_patterns(display: &DisplayDefinition, entry: &Entry) -> (Vec<String>, Vec<String>) {
+    let mut steps = MAX_SEARCH_STEPS;
+    let conflict = (1..=SMALL_CONFLICT)
+        .find_map(|size| conflict_of_size(display, entry, size, &mut Vec::new(), &mut steps))
+        .unwrap_or_else(|| minimal_conflict(display, entry, &mut steps));
+
+    let names = |patterns: &[u32]| {
+        patterns
+            .iter()
+            .map(|mask| display.mask_to_pattern(*mask))
+            .collect()
+    };
+    (names(&conflict.signals), names(&conflict.outputs))
+}
+
+fn diagnose(display: &DisplayDefinition, line: &str) -> Diagnosis {
+    let entry = match parse_entry(display, line) {
+        Ok(entry) => entry,
+        Err(reason) => return Diagnosis::Malformed(reason),
+    };
+
+    // an under-constrained line can fit a huge number of wirings, so the
+    // search stops once it's found one more than it keeps, and a line that
+    // takes too long to search at all is left undecided
+    let mut steps = MAX_SEARCH_STEPS;
+    let Found {
+        wirings: mut found,
+        gave_up,
+    } = wirings(display, &entry, MAX_CANDIDATES + 1, &mut steps);
+    let more = found.len() > MAX_CANDIDATES || gave_up;
+    found.truncate(MAX_CANDIDATES);
+    match found.len() {
+        0 | 1 if gave_up => Diagnosis::Undecided {
+            steps: MAX_SEARCH_STEPS,
+        },
+        0 => {
+            let (signals, outputs) = conflicting_patterns(display, &entry);
+            Diagnosis::Inconsistent { signals, outputs }
+        }
+        1 => Diagnosis::Solved(read(display, &entry, found.remove(0))),
+        _ => Diagnosis::Ambiguous {
+            candidates: found
+                .into_iter()
+                .map(|wiring| read(display, &entry, wiring))
+                .collect(),
+            more,
+        },
+    }
 }
 
 fn main() -> Result<()> {
     let text = read_text("8/input.txt")?;
 
+    let display = match std::env::args().skip(1).collect::<Vec<String>>().as_slice() {
+        [] => DisplayDefinition::seven_segment(),
+        [flag, path] if flag == "--display" => match DisplayDefinition::parse(&read_text(path)?) {
+            Ok(display) => display,
+            Err(reason) => panic!("Invalid display definition: {}", reason),
+        },
+        args => panic!("Unknown arguments {:?}", args),
+    };
+
     let mut p1_sum = 0;
     let mut p2_sum = 0;
+    let mut solved = 0;
+    let mut problems = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        match diagnose(&display, line) {
+            Diagnosis::Solved(decoded) => {
+                solved += 1;
+                p1_sum += decoded
+                    .outputs
+                    .iter()
+                    .filter(|symbol| display.has_unique_length(**symbol))
+                    .count();
+                // only numeric readings can be added up
+                p2_sum += decoded.reading.parse::<usize>().unwrap_or(0);
+            }
+            diagnosis => problems.push((i + 1, diagnosis)),
+        }
+    }
 
-    for line in text.lines() {
-        let mut parts = line.split(" | ");
-        let signal_patterns = parts.next().unwrap().split(" ").collect::<Vec<&str>>();
+    println!("{}", p1_sum);
+    println!("{}", p2_sum);
 
-        let output_patterns = parts.next().unwrap().split(" ").collect::<Vec<&str>>();
+    let count = |matches: fn(&Diagnosis) -> bool| {
+        problems
+            .iter()
+            .filter(|(_, diagnosis)| matches(diagnosis))
+            .count()
+    };
+    println!(
+        "solved {}, ambiguous {}, inconsistent {}, malformed {}, undecided {}",
+        solved,
+        count(|diagnosis| matches!(diagnosis, Diagnosis::Ambiguous { .. })),
+        count(|diagnosis| matches!(diagnosis, Diagnosis::Inconsistent { .. })),
+        count(|diagnosis| matches!(diagnosis, Diagnosis::Malformed(_))),
+        count(|diagnosis| matches!(diagnosis, Diagnosis::Undecided { .. }))
+    );
+    for (line_number, diagnosis) in &problems {
+        println!("line {}: {}", line_number, diagnosis);
+    }
 
-        // p1 stuff
-        for pattern in &output_patterns {
-            match pattern.len() {
-                2 => {
-                    // it's a 1
-                    p1_sum += 1;
-                }
-                3 => {
-                    // a 7
-                    p1_sum += 1;
-                }
-                4 => {
-                    // a 4
-                    p1_sum += 1;
-                }
-                7 => {
-                    // an 8
-                    p1_sum += 1;
-                }
-                _ => {}
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str =
+        "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf";
+
+    #[test]
+    fn test_decode() {
+        let decoded = match diagnose(&DisplayDefinition::seven_segment(), EXAMPLE) {
+            Diagnosis::Solved(decoded) => decoded,
+            diagnosis => panic!("{}", diagnosis),
+        };
+        assert_eq!(decoded.reading, "5353");
+        assert_eq!(decoded.outputs, vec![5, 3, 5, 3]);
+        // d drives the top segment and a the top right one
+        assert_eq!(decoded.wiring[3], 0);
+        assert_eq!(decoded.wiring[0], 2);
+    }
+
+    #[test]
+    fn test_inconsistent_names_the_conflict() {
+        // two patterns of length two can't both be a 1
+        assert_eq!(
+            diagnose(
+                &DisplayDefinition::seven_segment(),
+                "ab ac dab eafb cdfbe gcdfa fbcad cefabd cdfgeb acedgfb | ab ab ab ab"
+            ),
+            Diagnosis::Inconsistent {
+                signals: vec!["ab".to_string(), "ac".to_string()],
+                outputs: vec![],
             }
+        );
+        // b drives segment f here, and every six segment digit lights f
+        match diagnose(
+            &DisplayDefinition::seven_segment(),
+            "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | acdefg",
+        ) {
+            Diagnosis::Inconsistent { signals, outputs } => {
+                assert!(signals.len() <= 2);
+                assert_eq!(outputs, vec!["acdefg".to_string()]);
+            }
+            diagnosis => panic!("{}", diagnosis),
         }
+    }
 
-        // the map where we store character substitutions
-        let mut transposition_map = HashMap::new();
-        // pass through all singular patterns in here to determine the numbers
-        let mut number_to_digits = HashMap::new();
-        // change the pattern values into hash sets so we can search & iterate more easily
-        let signal_patterns: Vec<HashSet<String>> = signal_patterns
-            .iter()
-            .map(|pattern| pattern_to_set(pattern))
-            .collect();
-        for pattern in &signal_patterns {
-            match pattern.len() {
-                2 => {
-                    number_to_digits.insert(1, pattern.clone());
-                }
-                3 => {
-                    number_to_digits.insert(7, pattern.clone());
-                }
-                4 => {
-                    number_to_digits.insert(4, pattern.clone());
-                }
-                7 => {
-                    number_to_digits.insert(8, pattern.clone());
-                }
-                _ => {}
-            }
+    #[test]
+    fn test_ambiguous_lists_candidates() {
+        // a 1 and a 7 alone can't tell most of the wires apart
+        let candidates = match diagnose(&DisplayDefinition::seven_segment(), "ab abc | ab abc") {
+            Diagnosis::Ambiguous {
+                candidates,
+                more: false,
+            } => candidates,
+            diagnosis => panic!("{}", diagnosis),
+        };
+        assert_eq!(candidates.len(), 2 * 24);
+        assert!(candidates.iter().all(|candidate| candidate.reading == "17"));
+    }
+
+    #[test]
+    fn test_ambiguous_stops_at_the_cap() {
+        // an 8 on its own fits all 5040 wirings
+        let diagnosis = diagnose(&DisplayDefinition::seven_segment(), "abcdefg | abcdefg");
+        match &diagnosis {
+            Diagnosis::Ambiguous {
+                candidates,
+                more: true,
+            } => assert_eq!(candidates.len(), MAX_CANDIDATES),
+            diagnosis => panic!("{}", diagnosis),
         }
 
-        let digits_for_one = get_from_number_map(&number_to_digits, 1, &signal_patterns);
-        // find what the transpoition is for a
-        for digit in get_from_number_map(&number_to_digits, 7, &signal_patterns) {
-            if !digits_for_one.contains(digit) {
-                transposition_map.insert("a".to_string(), digit.to_owned());
+        let report = diagnosis.to_string();
+        assert!(report.starts_with(&format!("ambiguous, at least {} wirings:", MAX_CANDIDATES)));
+        assert_eq!(report.matches("reads").count(), SHOWN_CANDIDATES);
+        assert!(report.ends_with(" ..."));
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(matches!(
+            diagnose(&DisplayDefinition::seven_segment(), "ab abc"),
+            Diagnosis::Malformed(_)
+        ));
+        assert!(matches!(
+            diagnose(&DisplayDefinition::seven_segment(), "ab abz | ab"),
+            Diagnosis::Malformed(_)
+        ));
+        assert!(matches!(
+            diagnose(&DisplayDefinition::seven_segment(), "aab | ab"),
+            Diagnosis::Malformed(_)
+        ));
+    }
+
+    const HEX: &str = "segments abcdefg
+0 abcefg
+1 cf
+2 acdeg
+3 acdfg
+4 bcdf
+5 abdfg
+6 abdefg
+7 acf
+8 abcdefg
+9 abcdfg
+A abcdef
+b bdefg
+C abeg
+d cdefg
+E abdeg
+F abde";
+
+    // A 14 segment display with a handful of letters
+    const FOURTEEN_SEGMENT: &str = "segments abcdefghijklmn
+A abcefgh
+H bcefgh
+I adjm
+L def
+T ajm
+X iknl
+Z adin
+O abcdef
+E adefg
+F aefg";
+
+    // Scrambles each symbol's segments through the wiring, the way the
+    // puzzle input is generated
+    fn scramble(display: &DisplayDefinition, wiring: &[usize], symbols: &str) -> String {
+        let inverse: Vec<usize> = (0..wiring.len())
+            .map(|segment| wiring.iter().position(|s| *s == segment).unwrap())
+            .collect();
+        symbols
+            .chars()
+            .map(|symbol| {
+                let (_, mask) = display
+                    .symbols
+                    .iter()
+                    .find(|(name, _)| name == &symbol.to_string())
+                    .unwrap();
+                display.mask_to_pattern(rewire(*mask, &inverse))
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn test_hex_display() {
+        let display = DisplayDefinition::parse(HEX).unwrap();
+        let wiring = vec![4, 0, 6, 2, 5, 1, 3];
+        let line = format!(
+            "{} | {}",
+            scramble(&display, &wiring, "0123456789AbCdEF"),
+            scramble(&display, &wiring, "C0FFEE")
+        );
+
+        match diagnose(&display, &line) {
+            Diagnosis::Solved(decoded) => {
+                assert_eq!(decoded.reading, "C0FFEE");
+                assert_eq!(decoded.wiring, wiring);
             }
+            diagnosis => panic!("{}", diagnosis),
         }
+    }
 
-        solve_digit_for_g(
-            &signal_patterns,
-            &mut transposition_map,
-            &mut number_to_digits,
-        );
-        solve_digit_for_e(
-            &signal_patterns,
-            &mut transposition_map,
-            &mut number_to_digits,
-        );
-        find_six(&signal_patterns, &mut number_to_digits);
-        find_zero(
-            &signal_patterns,
-            &mut transposition_map,
-            &mut number_to_digits,
-        );
-        solve_digit_for_f(
-            &signal_patterns,
-            &mut transposition_map,
-            &mut number_to_digits,
-        );
-        solve_digit_for_b(
-            &signal_patterns,
-            &mut transposition_map,
-            &mut number_to_digits,
+    #[test]
+    fn test_fourteen_segment_display() {
+        let display = DisplayDefinition::parse(FOURTEEN_SEGMENT).unwrap();
+        let wiring = vec![13, 2, 7, 0, 11, 4, 9, 1, 12, 3, 6, 10, 5, 8];
+        let line = format!(
+            "{} | {}",
+            scramble(&display, &wiring, "AHILTXZOEF"),
+            scramble(&display, &wiring, "HELLO")
         );
 
-        let mut digit = Vec::new();
-        for pattern in &output_patterns {
-            let set = pattern_to_set(pattern);
-            for (num, solved_pattern) in &number_to_digits {
-                if set == *solved_pattern {
-                    digit.push(num.to_string());
-                }
+        // five pairs of segments are only ever lit together, so each pair's
+        // wires can swap and 2^5 wirings fit, all reading the same
+        match diagnose(&display, &line) {
+            Diagnosis::Ambiguous {
+                candidates,
+                more: false,
+            } => {
+                assert_eq!(candidates.len(), 32);
+                assert!(candidates
+                    .iter()
+                    .all(|candidate| candidate.reading == "HELLO"));
+                assert!(candidates
+                    .iter()
+                    .any(|candidate| candidate.wiring == wiring));
             }
+            diagnosis => panic!("{}", diagnosis),
         }
+    }
 
-        if digit.len() != 4 {
-            panic!("Invalid digit set {:?}", output_patterns);
+    #[test]
+    fn test_fourteen_segment_sparse_line() {
+        // one pattern barely narrows down 14! wirings, so the search has to
+        // stop at the cap rather than list them all
+        let display = DisplayDefinition::parse(FOURTEEN_SEGMENT).unwrap();
+        match diagnose(&display, "adefg | adefg") {
+            Diagnosis::Ambiguous {
+                candidates,
+                more: true,
+            } => {
+                assert_eq!(candidates.len(), MAX_CANDIDATES);
+                assert!(candidates.iter().all(|candidate| candidate.reading == "E"));
+            }
+            diagnosis => panic!("{}", diagnosis),
         }
+    }
 
-        p2_sum += digit.join("").parse::<i32>().unwrap();
+    #[test]
+    fn test_fourteen_segment_inconsistent_line() {
+        // two identical signals can't be different symbols, which has to be
+        // seen without trying all the wirings of the other eleven wires
+        let display = DisplayDefinition::parse(FOURTEEN_SEGMENT).unwrap();
+        assert_eq!(
+            diagnose(&display, "abc abc | abc"),
+            Diagnosis::Inconsistent {
+                signals: vec!["abc".to_string(), "abc".to_string()],
+                outputs: vec![],
+            }
+        );
     }
 
-    println!("{}", p1_sum);
-    println!("{}", p2_sum);
+    #[test]
+    fn test_undecided_past_the_step_limit() {
+        // the search tries at least one wire per step, so with no steps left
+        // it can't decide anything
+        let display = DisplayDefinition::seven_segment();
+        let entry = parse_entry(&display, EXAMPLE).unwrap();
+        let found = wirings(&display, &entry, 2, &mut 0);
+        assert!(found.gave_up && found.wirings.is_empty() && !found.none_fit());
+        assert_eq!(
+            Diagnosis::Undecided { steps: 10 }.to_string(),
+            "undecided, gave up after 10 search steps"
+        );
+    }
 
-    Ok(())
+    #[test]
+    fn test_invalid_display_definitions() {
+        assert!(DisplayDefinition::parse("0 abc").is_err());
+        assert!(DisplayDefinition::parse("segments aab\n0 a").is_err());
+        assert!(DisplayDefinition::parse("segments abc\n0 ab\n1 ba").is_err());
+        assert!(DisplayDefinition::parse("segments abc\n0 az").is_err());
+    }
 }
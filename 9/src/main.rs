@@ -1,115 +1,721 @@
-use std::cmp;
-use std::collections::{HashMap, HashSet, VecDeque};
-use std::io::Result;
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
 
 use read_input::read_text;
 
-type Pos = (i32, i32);
+// (col, row)
+type Pos = (usize, usize);
 
-fn get_adjacents(height_map: &HashMap<Pos, u32>, pos: &Pos) -> Vec<Pos> {
-    let mut adjacents = Vec::new();
+struct HeightMap {
+    width: usize,
+    height: usize,
+    // row by row
+    cells: Vec<u32>,
+}
 
-    if height_map.contains_key(&(pos.0 - 1, pos.1)) {
-        adjacents.push((pos.0 - 1, pos.1));
+impl HeightMap {
+    fn parse(text: &str) -> Self {
+        let mut width = 0;
+        let mut cells = Vec::new();
+        for (row, line) in text.lines().enumerate() {
+            let heights: Vec<u32> = line.chars().map(|ch| ch.to_digit(10).unwrap()).collect();
+            if row == 0 {
+                width = heights.len();
+            } else if heights.len() != width {
+                panic!(
+                    "Row {} has {} cells, expected {}",
+                    row,
+                    heights.len(),
+                    width
+                );
+            }
+            cells.extend(heights);
+        }
+
+        HeightMap {
+            width,
+            height: cells.len().checked_div(width).unwrap_or(0),
+            cells,
+        }
     }
-    if height_map.contains_key(&(pos.0, pos.1 - 1)) {
-        adjacents.push((pos.0, pos.1 - 1));
+
+    fn index(&self, pos: Pos) -> usize {
+        pos.1 * self.width + pos.0
     }
-    if height_map.contains_key(&(pos.0 + 1, pos.1)) {
-        adjacents.push((pos.0 + 1, pos.1));
+
+    fn pos(&self, index: usize) -> Pos {
+        (index % self.width, index / self.width)
     }
-    if height_map.contains_key(&(pos.0, pos.1 + 1)) {
-        adjacents.push((pos.0, pos.1 + 1));
+
+    fn get(&self, pos: Pos) -> u32 {
+        self.cells[self.index(pos)]
     }
 
-    adjacents
-}
+    fn get_adjacents(&self, pos: Pos, connectivity: Connectivity) -> Vec<Pos> {
+        let offsets: &[(isize, isize)] = match connectivity {
+            Connectivity::Four => &[(0, -1), (-1, 0), (1, 0), (0, 1)],
+            Connectivity::Eight => &[
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+                (-1, 0),
+                (1, 0),
+                (-1, 1),
+                (0, 1),
+                (1, 1),
+            ],
+        };
 
-fn get_coords_for_basin(
-    height_map: &HashMap<Pos, u32>,
-    crawled_basins: &mut HashSet<(i32, i32)>,
-    pos: &Pos,
-) -> usize {
-    let mut crawled_positions_for_basin = HashSet::new();
-
-    let mut positions = VecDeque::from([pos.to_owned()]);
-    while positions.len() > 0 {
-        let pos = positions.pop_front().unwrap();
-        crawled_positions_for_basin.insert(pos.clone());
-        let adjacents = get_adjacents(height_map, &pos)
+        offsets
             .iter()
-            .filter(|pos| {
-                !crawled_positions_for_basin.contains(pos)
-                    && !crawled_basins.contains(pos)
-                    && *height_map.get(pos).unwrap() != 9
+            .filter_map(|(dc, dr)| {
+                let col = pos.0.checked_add_signed(*dc)?;
+                let row = pos.1.checked_add_signed(*dr)?;
+                if col < self.width && row < self.height {
+                    Some((col, row))
+                } else {
+                    None
+                }
             })
-            .cloned()
-            .collect::<Vec<Pos>>();
+            .collect()
+    }
 
-        positions.append(&mut VecDeque::from(adjacents));
+    // A "col,row" argument that has to be on the map
+    fn parse_pos(&self, arg: Option<String>) -> Pos {
+        let pos = arg
+            .and_then(|pos| {
+                let (col, row) = pos.split_once(',')?;
+                Some((col.parse::<usize>().ok()?, row.parse::<usize>().ok()?))
+            })
+            .expect("Expected a position like 3,4");
+        if pos.0 >= self.width || pos.1 >= self.height {
+            panic!("{:?} is outside the height map", pos);
+        }
+        pos
     }
 
-    for pos in &crawled_positions_for_basin {
-        crawled_basins.insert(pos.to_owned());
+    fn is_wall(&self, pos: Pos, rules: &Rules) -> bool {
+        self.get(pos) >= rules.wall_height
+    }
+
+    // Groups cells into flat areas of equal height and keeps the areas with no
+    // lower neighbour anywhere along their edge. A single cell lower than all
+    // its neighbours is an area of one. Each area's cells are in reading order.
+    fn minima(&self, connectivity: Connectivity) -> Vec<Vec<Pos>> {
+        let mut sets = DisjointSet::new(self.cells.len());
+        for index in 0..self.cells.len() {
+            let pos = self.pos(index);
+            for adjacent in self.get_adjacents(pos, connectivity) {
+                let other = self.index(adjacent);
+                if other < index && self.cells[other] == self.cells[index] {
+                    sets.union(index, other);
+                }
+            }
+        }
+
+        let mut drains = vec![false; self.cells.len()];
+        for index in 0..self.cells.len() {
+            let pos = self.pos(index);
+            if self
+                .get_adjacents(pos, connectivity)
+                .iter()
+                .any(|adjacent| self.get(*adjacent) < self.cells[index])
+            {
+                let root = sets.find(index);
+                drains[root] = true;
+            }
+        }
+
+        let mut ids = vec![None; self.cells.len()];
+        let mut areas: Vec<Vec<Pos>> = Vec::new();
+        for index in 0..self.cells.len() {
+            let root = sets.find(index);
+            if drains[root] {
+                continue;
+            }
+            let id = *ids[root].get_or_insert_with(|| {
+                areas.push(Vec::new());
+                areas.len() - 1
+            });
+            areas[id].push(self.pos(index));
+        }
+
+        areas
+    }
+
+    // The minima that aren't walls
+    fn low_areas(&self, rules: &Rules) -> Vec<Vec<Pos>> {
+        self.minima(rules.connectivity)
+            .into_iter()
+            .filter(|area| !self.is_wall(area[0], rules))
+            .collect()
+    }
+
+    // Each low area counts once, at its height plus one
+    fn risk_sum(&self, rules: &Rules) -> u32 {
+        self.low_areas(rules)
+            .iter()
+            .map(|area| self.get(area[0]) + 1)
+            .sum()
     }
-    crawled_positions_for_basin.len()
 }
 
-fn main() -> Result<()> {
-    let text = read_text("9/input.txt")?;
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Connectivity {
+    // sides only
+    Four,
+    // sides and corners
+    Eight,
+}
+
+#[derive(Debug, PartialEq)]
+struct Rules {
+    connectivity: Connectivity,
+    // cells at least this high separate basins
+    wall_height: u32,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            connectivity: Connectivity::Four,
+            wall_height: 9,
+        }
+    }
+}
+
+// Union-find over cell indexes with path halving and union by size
+struct DisjointSet {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl DisjointSet {
+    fn new(len: usize) -> Self {
+        DisjointSet {
+            parent: (0..len).collect(),
+            size: vec![1; len],
+        }
+    }
 
-    let mut height_map = HashMap::new();
-    let mut max_row: i32 = 0;
-    let mut max_col: i32 = 0;
-    for (row, line) in text.lines().enumerate() {
-        max_row = cmp::max(max_row, row as i32);
-        for (col, ch) in line.chars().enumerate() {
-            max_col = cmp::max(max_col, col as i32);
-            height_map.insert((col as i32, row as i32), ch.to_digit(10).unwrap());
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
         }
+        i
     }
 
-    let mut risk_sum = 0;
-    let mut basin_sizes = Vec::new();
-    let mut crawled_basins = HashSet::new();
-    for col in 0..=max_col {
-        for row in 0..=max_row {
-            let height = height_map.get(&(col, row)).unwrap();
-            if *height == 9 {
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        let (big, small) = if self.size[a] >= self.size[b] {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        self.parent[small] = big;
+        self.size[big] += self.size[small];
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Basin {
+    size: usize,
+    // the lowest cell, the first in reading order if several share the height
+    low_point: Pos,
+    // top left and bottom right corners
+    bounds: (Pos, Pos),
+}
+
+impl fmt::Display for Basin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ((left, top), (right, bottom)) = self.bounds;
+        write!(
+            f,
+            "size {}, low point {:?}, spanning ({}, {}) to ({}, {})",
+            self.size, self.low_point, left, top, right, bottom
+        )
+    }
+}
+
+struct Basins {
+    // labels[index] is the basin a cell belongs to, None for walls
+    labels: Vec<Option<usize>>,
+    basins: Vec<Basin>,
+}
+
+impl Basins {
+    // Joins every cell with its neighbours above and to the left in a single
+    // pass, then numbers the basins in reading order of their first cell
+    fn label(height_map: &HeightMap, rules: &Rules) -> Self {
+        let mut sets = DisjointSet::new(height_map.cells.len());
+        for index in 0..height_map.cells.len() {
+            let pos = height_map.pos(index);
+            if height_map.is_wall(pos, rules) {
+                continue;
+            }
+            for adjacent in height_map.get_adjacents(pos, rules.connectivity) {
+                let other = height_map.index(adjacent);
+                if other < index && !height_map.is_wall(adjacent, rules) {
+                    sets.union(index, other);
+                }
+            }
+        }
+
+        let mut ids = vec![None; height_map.cells.len()];
+        let mut labels = vec![None; height_map.cells.len()];
+        let mut basins: Vec<Basin> = Vec::new();
+        for (index, label) in labels.iter_mut().enumerate() {
+            let pos = height_map.pos(index);
+            if height_map.is_wall(pos, rules) {
                 continue;
             }
 
-            let point = height_map.get(&(col, row)).unwrap();
-            let adjacents = get_adjacents(&height_map, &(col, row));
-            let is_low_point = adjacents.iter().fold(true, |result, pos| {
-                result && *height_map.get(pos).unwrap() > *point
+            let root = sets.find(index);
+            let id = *ids[root].get_or_insert_with(|| {
+                basins.push(Basin {
+                    size: 0,
+                    low_point: pos,
+                    bounds: (pos, pos),
+                });
+                basins.len() - 1
             });
+            *label = Some(id);
 
-            if is_low_point {
-                risk_sum += *point + 1;
+            let basin = &mut basins[id];
+            basin.size += 1;
+            if height_map.get(pos) < height_map.get(basin.low_point) {
+                basin.low_point = pos;
             }
+            let ((left, top), (right, bottom)) = basin.bounds;
+            basin.bounds = (
+                (left.min(pos.0), top.min(pos.1)),
+                (right.max(pos.0), bottom.max(pos.1)),
+            );
+        }
+
+        Basins { labels, basins }
+    }
+
+    fn basin_at(&self, height_map: &HeightMap, pos: Pos) -> Option<&Basin> {
+        self.labels[height_map.index(pos)].map(|id| &self.basins[id])
+    }
+
+    // The k largest basins, biggest first, ties in label order
+    fn largest(&self, k: usize) -> Vec<&Basin> {
+        let mut basins: Vec<&Basin> = self.basins.iter().collect();
+        basins.sort_by_key(|basin| Reverse(basin.size));
+        basins.truncate(k);
+        basins
+    }
+
+    fn largest_product(&self, k: usize) -> usize {
+        self.largest(k).iter().map(|basin| basin.size).product()
+    }
+}
+
+// Where the rain falling on each cell ends up when every cell drains to its
+// lowest neighbour. Walls don't stop water, they're just high ground.
+struct Watershed {
+    width: usize,
+    // downstream[index] is the cell it drains into, None for cells in a minimum
+    downstream: Vec<Option<usize>>,
+    // cells with more than one equally good way down, which drain into the
+    // first of them in reading order
+    ambiguous: Vec<bool>,
+    // catchment[index] is the minimum the cell's rain ends up in
+    catchment: Vec<usize>,
+    // how many cells' rain passes through each cell, its own included
+    accumulation: Vec<usize>,
+    minima: Vec<Vec<Pos>>,
+}
+
+impl Watershed {
+    fn simulate(height_map: &HeightMap, connectivity: Connectivity) -> Self {
+        let len = height_map.cells.len();
+        let height = |index: usize| height_map.cells[index];
+        let neighbours = |index: usize| {
+            height_map
+                .get_adjacents(height_map.pos(index), connectivity)
+                .into_iter()
+                .map(|pos| height_map.index(pos))
+                .collect::<Vec<usize>>()
+        };
 
-            if !crawled_basins.contains(&(col, row)) {
-                let size = get_coords_for_basin(&height_map, &mut crawled_basins, &(col, row));
-                basin_sizes.push(size);
+        // Cells with a lower neighbour drain to the lowest one. On a flat
+        // area that isn't a minimum, cells drain towards the nearest edge
+        // that leads down, so distance[index] counts the flat steps to one.
+        let mut downstream = vec![None; len];
+        let mut ambiguous = vec![false; len];
+        let mut distance = vec![None; len];
+        let mut queue = VecDeque::new();
+        for index in 0..len {
+            let lowest = neighbours(index).into_iter().map(height).min();
+            if let Some(lowest) = lowest.filter(|lowest| *lowest < height(index)) {
+                let mut ways_down = neighbours(index)
+                    .into_iter()
+                    .filter(|other| height(*other) == lowest);
+                downstream[index] = ways_down.next();
+                ambiguous[index] = ways_down.next().is_some();
+                distance[index] = Some(0);
+                queue.push_back(index);
             }
         }
+        while let Some(index) = queue.pop_front() {
+            for other in neighbours(index) {
+                if distance[other].is_none() && height(other) == height(index) {
+                    distance[other] = Some(distance[index].unwrap() + 1);
+                    queue.push_back(other);
+                }
+            }
+        }
+        for index in 0..len {
+            if let Some(steps) = distance[index].filter(|steps| *steps > 0) {
+                let mut ways_on = neighbours(index).into_iter().filter(|other| {
+                    height(*other) == height(index) && distance[*other] == Some(steps - 1)
+                });
+                downstream[index] = ways_on.next();
+                ambiguous[index] = ways_on.next().is_some();
+            }
+        }
+
+        // Every cell drains into one that is lower, or as high and nearer the
+        // way down, so going from the top down visits each cell after
+        // everything that drains into it
+        let mut order: Vec<usize> = (0..len).collect();
+        order.sort_by_key(|index| Reverse((height(*index), distance[*index])));
+
+        let mut accumulation = vec![1; len];
+        for index in &order {
+            if let Some(next) = downstream[*index] {
+                accumulation[next] += accumulation[*index];
+            }
+        }
+
+        let minima = height_map.minima(connectivity);
+        let mut catchment = vec![0; len];
+        for (id, area) in minima.iter().enumerate() {
+            for pos in area {
+                catchment[height_map.index(*pos)] = id;
+            }
+        }
+        for index in order.iter().rev() {
+            if let Some(next) = downstream[*index] {
+                catchment[*index] = catchment[next];
+            }
+        }
+
+        Watershed {
+            width: height_map.width,
+            downstream,
+            ambiguous,
+            catchment,
+            accumulation,
+            minima,
+        }
     }
 
-    basin_sizes.sort_by(|a, b| {
-        if a > b {
-            cmp::Ordering::Less
-        } else if a < b {
-            cmp::Ordering::Greater
-        } else {
-            cmp::Ordering::Equal
+    // The cells a drop of rain passes through on its way to a minimum
+    fn path_from(&self, mut index: usize) -> Vec<usize> {
+        let mut path = vec![index];
+        while let Some(next) = self.downstream[index] {
+            path.push(next);
+            index = next;
         }
-    });
-    println!("{}", risk_sum);
-    println!(
-        "{}",
-        basin_sizes[0..3].iter().fold(1, |prod, size| prod * size)
-    );
+        path
+    }
+
+    fn catchment_sizes(&self) -> Vec<usize> {
+        self.catchment
+            .iter()
+            .fold(vec![0; self.minima.len()], |mut sizes, id| {
+                sizes[*id] += 1;
+                sizes
+            })
+    }
+
+    // One right aligned value per cell, row by row
+    fn write_grid(&self, out: &mut impl Write, values: Vec<String>) -> Result<()> {
+        let width = values.iter().map(|value| value.len()).max().unwrap_or(0);
+        for row in values.chunks(self.width.max(1)) {
+            let cells: Vec<String> = row
+                .iter()
+                .map(|value| format!("{:>width$}", value, width = width))
+                .collect();
+            writeln!(out, "{}", cells.join(" "))?;
+        }
+        Ok(())
+    }
+
+    // Each cell's catchment, numbered like `minima`, with a * after cells
+    // that could have drained another way
+    fn write_catchments(&self, out: &mut impl Write) -> Result<()> {
+        let values = self
+            .catchment
+            .iter()
+            .zip(&self.ambiguous)
+            .map(|(id, ambiguous)| format!("{}{}", id, if *ambiguous { "*" } else { "" }))
+            .collect();
+        self.write_grid(out, values)
+    }
+
+    fn write_accumulation(&self, out: &mut impl Write) -> Result<()> {
+        let values = self
+            .accumulation
+            .iter()
+            .map(|count| count.to_string())
+            .collect();
+        self.write_grid(out, values)
+    }
+}
+
+fn main() -> Result<()> {
+    let text = read_text("9/input.txt")?;
+    let height_map = HeightMap::parse(&text);
+
+    // --diagonals joins cells at their corners too, --wall H makes every
+    // height from H up a wall
+    let mut rules = Rules::default();
+    let mut catchments = None;
+    let mut accumulation = None;
+    let mut queries = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--diagonals" => rules.connectivity = Connectivity::Eight,
+            "--wall" => {
+                rules.wall_height = args
+                    .next()
+                    .and_then(|height| height.parse::<u32>().ok())
+                    .expect("--wall needs a height")
+            }
+            // grids of where each cell's rain ends up and how much passes
+            // through it
+            "--catchments" => catchments = Some(args.next().expect("--catchments needs a path")),
+            "--accumulation" => {
+                accumulation = Some(args.next().expect("--accumulation needs a path"))
+            }
+            _ => queries.push(arg),
+        }
+    }
+
+    let basins = Basins::label(&height_map, &rules);
+
+    println!("{}", height_map.risk_sum(&rules));
+    println!("{}", basins.largest_product(3));
+
+    // --top K lists the K largest basins, --at COL,ROW the basin a cell is in
+    // and --rain COL,ROW the way rain falling there flows
+    let watershed = Watershed::simulate(&height_map, rules.connectivity);
+    let mut args = queries.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--top" => {
+                let k = args
+                    .next()
+                    .and_then(|k| k.parse::<usize>().ok())
+                    .expect("--top needs a number");
+                for basin in basins.largest(k) {
+                    println!("{}", basin);
+                }
+                println!("product {}", basins.largest_product(k));
+            }
+            "--at" => {
+                let pos = height_map.parse_pos(args.next());
+                match basins.basin_at(&height_map, pos) {
+                    Some(basin) => println!("{}", basin),
+                    None => println!("{:?} is a wall", pos),
+                }
+            }
+            "--rain" => {
+                let pos = height_map.parse_pos(args.next());
+                let path: Vec<Pos> = watershed
+                    .path_from(height_map.index(pos))
+                    .into_iter()
+                    .map(|index| height_map.pos(index))
+                    .collect();
+                println!("{:?}", path);
+            }
+            _ => panic!("Unknown argument {}", arg),
+        }
+    }
+
+    if catchments.is_some() || accumulation.is_some() {
+        println!(
+            "{} catchments, the largest {} cells, {} cells drain ambiguously",
+            watershed.minima.len(),
+            watershed.catchment_sizes().iter().max().unwrap_or(&0),
+            watershed
+                .ambiguous
+                .iter()
+                .filter(|ambiguous| **ambiguous)
+                .count()
+        );
+        if let Some(path) = catchments {
+            watershed.write_catchments(&mut BufWriter::new(File::create(path)?))?;
+        }
+        if let Some(path) = accumulation {
+            watershed.write_accumulation(&mut BufWriter::new(File::create(path)?))?;
+        }
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "2199943210
+3987894921
+9856789892
+8767896789
+9899965678";
+
+    #[test]
+    fn test_low_areas() {
+        let height_map = HeightMap::parse(EXAMPLE);
+        let rules = Rules::default();
+        assert_eq!(
+            height_map.low_areas(&rules),
+            vec![vec![(1, 0)], vec![(9, 0)], vec![(2, 2)], vec![(6, 4)]]
+        );
+        assert_eq!(height_map.risk_sum(&rules), 15);
+    }
+
+    #[test]
+    fn test_plateaus() {
+        // a flat minimum of three cells and a flat shelf that drains into it
+        let height_map = HeightMap::parse(
+            "99999
+91119
+92229
+99999",
+        );
+        let rules = Rules::default();
+        assert_eq!(
+            height_map.low_areas(&rules),
+            vec![vec![(1, 1), (2, 1), (3, 1)]]
+        );
+        assert_eq!(height_map.risk_sum(&rules), 2);
+
+        // diagonals only add more ways off the shelf
+        let rules = Rules {
+            connectivity: Connectivity::Eight,
+            ..Rules::default()
+        };
+        assert_eq!(height_map.low_areas(&rules).len(), 1);
+    }
+
+    #[test]
+    fn test_connectivity_and_walls() {
+        let height_map = HeightMap::parse(
+            "1919
+9191
+1919",
+        );
+        let four = Rules::default();
+        assert_eq!(Basins::label(&height_map, &four).basins.len(), 6);
+        let eight = Rules {
+            connectivity: Connectivity::Eight,
+            ..Rules::default()
+        };
+        assert_eq!(Basins::label(&height_map, &eight).basins.len(), 1);
+
+        // lowering the walls to 5 splits the example's big basin
+        let height_map = HeightMap::parse(EXAMPLE);
+        let low_walls = Rules {
+            wall_height: 5,
+            ..Rules::default()
+        };
+        let basins = Basins::label(&height_map, &low_walls);
+        assert!(basins.basins.iter().all(|basin| basin.size < 14));
+        assert!(basins
+            .basins
+            .iter()
+            .all(|basin| height_map.get(basin.low_point) < 5));
+    }
+
+    #[test]
+    fn test_basins() {
+        let height_map = HeightMap::parse(EXAMPLE);
+        let basins = Basins::label(&height_map, &Rules::default());
+
+        assert_eq!(basins.basins.len(), 4);
+        assert_eq!(basins.largest_product(3), 1134);
+        assert_eq!(
+            basins.largest(2),
+            vec![
+                &Basin {
+                    size: 14,
+                    low_point: (2, 2),
+                    bounds: ((0, 1), (5, 4)),
+                },
+                &Basin {
+                    size: 9,
+                    low_point: (9, 0),
+                    bounds: ((5, 0), (9, 2)),
+                },
+            ]
+        );
+        assert_eq!(basins.basin_at(&height_map, (0, 0)).unwrap().size, 3);
+        assert_eq!(basins.basin_at(&height_map, (2, 0)), None);
+        // every open cell is labelled exactly once
+        let labelled = basins.labels.iter().filter(|label| label.is_some()).count();
+        assert_eq!(
+            labelled,
+            basins.basins.iter().map(|basin| basin.size).sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn test_watershed() {
+        let height_map = HeightMap::parse(EXAMPLE);
+        let watershed = Watershed::simulate(&height_map, Connectivity::Four);
+        assert_eq!(watershed.minima.len(), 4);
+        assert_eq!(watershed.catchment_sizes().iter().sum::<usize>(), 50);
+        // all the rain collects in the minima
+        let collected: usize = watershed
+            .minima
+            .iter()
+            .flatten()
+            .map(|pos| watershed.accumulation[height_map.index(*pos)])
+            .sum();
+        assert_eq!(collected, 50);
+        assert!(watershed
+            .downstream
+            .iter()
+            .enumerate()
+            .all(|(index, next)| {
+                next.is_none_or(|next| height_map.cells[next] < height_map.cells[index])
+            }));
+    }
+
+    #[test]
+    fn test_flat_and_ambiguous_drainage() {
+        // the flat run of ones drains left, one step at a time
+        let height_map = HeightMap::parse("01112");
+        let watershed = Watershed::simulate(&height_map, Connectivity::Four);
+        assert_eq!(
+            watershed.downstream,
+            vec![None, Some(0), Some(1), Some(2), Some(3)]
+        );
+        assert_eq!(watershed.accumulation, vec![5, 4, 3, 2, 1]);
+        assert_eq!(watershed.path_from(4), vec![4, 3, 2, 1, 0]);
+        assert!(watershed.ambiguous.iter().all(|ambiguous| !ambiguous));
+
+        // the middle of the flat run and the 2 could each go either way
+        let height_map = HeightMap::parse("01110121");
+        let watershed = Watershed::simulate(&height_map, Connectivity::Four);
+        assert_eq!(watershed.minima.len(), 3);
+        let mut out = Vec::new();
+        watershed.write_catchments(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), " 0  0 0*  1  1  1 1*  2\n");
+    }
+}